@@ -0,0 +1,510 @@
+//! A tree-walking evaluator for the typed Core AST.
+//!
+//! This module is deliberately simple: it does not attempt to be fast, only
+//! correct. It exists so that `--phase eval` can actually execute a program
+//! after elaboration, rather than stopping once type-checking succeeds.
+
+use crate::builtin::{constructors, primitives};
+use crate::types::Constructor;
+use crate::{Decl, Expr, ExprKind, Lambda, Pat, PatKind, Rule};
+use sml_frontend::ast::Const;
+use sml_util::interner::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A runtime environment, mapping bound variables to the [`Value`] they
+/// currently denote. Closures hold it behind an `Rc<RefCell<..>>`: a plain
+/// `fn` captures its own clone of the environment, while the closures of
+/// one `fun` group share a single environment (see [`Value::Closure`]).
+pub type Env<'ar> = HashMap<Symbol, Value<'ar>>;
+
+/// A fully reduced runtime value.
+#[derive(Clone)]
+pub enum Value<'ar> {
+    /// A literal constant (integer, string, char, ...).
+    Const(Const),
+    /// A saturated application of a data constructor to its (optional)
+    /// argument, e.g. `SOME 3` or `[]`.
+    Constructor(Constructor, Option<Box<Value<'ar>>>),
+    /// A record value, keyed by field label.
+    Record(Vec<(Symbol, Value<'ar>)>),
+    /// A closure: the `Lambda` to apply, plus the environment in which its
+    /// body should be evaluated. The environment is shared (and, for
+    /// mutually recursive `fun` bindings, mutated once up front) via
+    /// `Rc<RefCell<..>>` so that a closure and the bindings it was built
+    /// to see can refer to each other.
+    Closure(Rc<Lambda<'ar>>, Rc<RefCell<Env<'ar>>>),
+    /// A builtin operation, partially applied to the arguments it's seen so
+    /// far. Dispatches through [`eval_primitive`] once it has as many
+    /// arguments as `primitive_arity` says it needs.
+    Primitive(Symbol, Vec<Value<'ar>>),
+}
+
+impl<'ar> fmt::Debug for Value<'ar> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Const(c) => write!(f, "{:?}", c),
+            Value::Constructor(con, Some(v)) => write!(f, "{:?} {:?}", con, v),
+            Value::Constructor(con, None) => write!(f, "{:?}", con),
+            Value::Record(rows) => {
+                write!(f, "{{")?;
+                for (i, (label, val)) in rows.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}={:?}", label, val)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Closure(..) => write!(f, "<fn>"),
+            Value::Primitive(sym, args) => write!(f, "<primitive {:?}/{}>", sym, args.len()),
+        }
+    }
+}
+
+/// Non-local control flow produced while evaluating an expression.
+pub enum Flow<'ar> {
+    /// An uncaught `raise`, carrying the raised value.
+    Raise(Value<'ar>),
+}
+
+pub type EvalResult<'ar> = Result<Value<'ar>, Flow<'ar>>;
+
+/// Evaluate a sequence of top-level declarations in order, threading the
+/// environment produced by each through to the next, and return the final
+/// environment.
+pub fn eval_decls<'ar>(decls: &[Decl<'ar>], env: &Env<'ar>) -> Result<Env<'ar>, Flow<'ar>> {
+    let mut env = env.clone();
+    for decl in decls {
+        eval_decl(decl, &mut env)?;
+    }
+    Ok(env)
+}
+
+fn eval_decl<'ar>(decl: &Decl<'ar>, env: &mut Env<'ar>) -> Result<(), Flow<'ar>> {
+    match decl {
+        Decl::Val(rule) => {
+            let value = eval_expr(&rule.expr, env)?;
+            match match_pattern(&rule.pat, &value) {
+                Some(bindings) => env.extend(bindings),
+                // A `val` pattern that doesn't match its right-hand side is
+                // a legal (if warned-about) SML program, just like a
+                // non-exhaustive `case` in `eval_rules`: it should raise
+                // `Bind`, catchable by an enclosing `handle`, rather than
+                // silently skip the binding and leave later references to
+                // hit `ExprKind::Var`'s "unbound variable" panic.
+                None => return Err(Flow::Raise(Value::Constructor(constructors::C_BIND, None))),
+            }
+        }
+        Decl::Fun(_, lambdas) => {
+            // Each `Lambda` here binds the *function's own name* via
+            // `.arg` (see `FreeVars::visit_decl`, which treats every
+            // `lambda.arg` in a `Decl::Fun` group as a bound name, not a
+            // parameter); `lambda.body` is the value that name is bound
+            // to, almost always itself an `ExprKind::Lambda` for the
+            // function's real parameter (`fun f x = e` elaborates to
+            // `Lambda { arg: f, body: Lambda { arg: x, body: e } }`).
+            // Wrapping `lam` itself, as if `lam.arg` were a parameter,
+            // silently turns every call into "apply `f` to `v`, getting
+            // back a fresh closure that ignores `v`" instead of running
+            // the function body.
+            //
+            // Mutually recursive functions must also see each other, so
+            // we tie the knot by building one shared, mutable environment
+            // and inserting each resulting value into it (as well as into
+            // the caller's `env`) as it's built. A closure only reads its
+            // captured environment when called, and by then every sibling
+            // in this group has already been inserted into `shared`, so
+            // it finds the real thing rather than a placeholder.
+            let shared = Rc::new(RefCell::new(env.clone()));
+            for lam in lambdas {
+                let value = match lam.body.expr {
+                    // Share `shared` itself, not a snapshot of it — the
+                    // general `ExprKind::Lambda` case in `eval_expr` would
+                    // instead capture a fresh, disconnected clone of
+                    // whatever env we hand it, which wouldn't see this
+                    // group's later insertions (or a self-reference).
+                    ExprKind::Lambda(inner) => Value::Closure(Rc::new(inner.clone()), shared.clone()),
+                    _ => eval_expr(&lam.body, &shared.borrow())?,
+                };
+                shared.borrow_mut().insert(lam.arg, value.clone());
+                env.insert(lam.arg, value);
+            }
+        }
+        Decl::Datatype(_) | Decl::Exn(_, _) => {
+            // Constructors and exceptions don't bind runtime values; they
+            // are only meaningful when applied, at which point `eval_expr`
+            // handles `ExprKind::Con` directly.
+        }
+    }
+    Ok(())
+}
+
+/// Evaluate a single expression to a [`Value`].
+pub fn eval_expr<'ar>(expr: &Expr<'ar>, env: &Env<'ar>) -> EvalResult<'ar> {
+    match expr.expr {
+        ExprKind::Const(c) => Ok(Value::Const(*c)),
+        ExprKind::Var(sym) => Ok(env
+            .get(sym)
+            .cloned()
+            .unwrap_or_else(|| panic!("eval: unbound variable {:?}", sym))),
+        ExprKind::Con(con, _) => Ok(Value::Constructor(*con, None)),
+        ExprKind::Lambda(lam) => Ok(Value::Closure(Rc::new(lam.clone()), Rc::new(RefCell::new(env.clone())))),
+        ExprKind::App(f, arg) => {
+            // `SOME x`, `x :: xs`, and friends are represented as the
+            // application of a constructor value to its argument.
+            let func = eval_expr(f, env)?;
+            let argv = eval_expr(arg, env)?;
+            match func {
+                Value::Constructor(con, None) => Ok(Value::Constructor(con, Some(Box::new(argv)))),
+                Value::Closure(lam, captured) => {
+                    let mut call_env = captured.borrow().clone();
+                    call_env.insert(lam.arg, argv);
+                    eval_expr(&lam.body, &call_env)
+                }
+                Value::Primitive(sym, mut args) => {
+                    args.push(argv);
+                    if args.len() == primitive_arity(sym) {
+                        eval_primitive(sym, &args)
+                    } else {
+                        Ok(Value::Primitive(sym, args))
+                    }
+                }
+                other => panic!("eval: attempted to apply a non-function value: {:?}", other),
+            }
+        }
+        ExprKind::Case(scrutinee, rules) => {
+            let value = eval_expr(scrutinee, env)?;
+            eval_rules(rules, &value, env)
+        }
+        ExprKind::Handle(body, rules) => match eval_expr(body, env) {
+            Ok(v) => Ok(v),
+            Err(Flow::Raise(v)) => eval_handler_rules(rules, v, env),
+        },
+        ExprKind::Let(decls, body) => {
+            let env = eval_decls(decls, env)?;
+            eval_expr(body, &env)
+        }
+        ExprKind::List(exprs) => {
+            let mut list = Value::Constructor(constructors::C_NIL, None);
+            for e in exprs.iter().rev() {
+                let v = eval_expr(e, env)?;
+                list = Value::Constructor(
+                    constructors::C_CONS,
+                    Some(Box::new(Value::Record(vec![
+                        (Symbol::tuple_field(1), v),
+                        (Symbol::tuple_field(2), list),
+                    ]))),
+                );
+            }
+            Ok(list)
+        }
+        ExprKind::Record(rows) => {
+            let mut out = Vec::with_capacity(rows.len());
+            for row in rows {
+                out.push((row.label, eval_expr(&row.data, env)?));
+            }
+            Ok(Value::Record(out))
+        }
+        ExprKind::Seq(exprs) => {
+            let mut last = None;
+            for e in exprs {
+                last = Some(eval_expr(e, env)?);
+            }
+            Ok(last.expect("eval: empty Seq"))
+        }
+        ExprKind::Raise(e) => {
+            let v = eval_expr(e, env)?;
+            Err(Flow::Raise(v))
+        }
+        ExprKind::Primitive(sym) => Ok(Value::Primitive(*sym, Vec::new())),
+    }
+}
+
+fn eval_rules<'ar>(rules: &[Rule<'ar>], value: &Value<'ar>, env: &Env<'ar>) -> EvalResult<'ar> {
+    for rule in rules {
+        if let Some(bindings) = match_pattern(&rule.pat, value) {
+            let mut env = env.clone();
+            env.extend(bindings);
+            return eval_expr(&rule.expr, &env);
+        }
+    }
+    // A non-exhaustive `case`/`fun` is a legal (if warned-about) SML
+    // program: it should raise `Match`, catchable by an enclosing
+    // `handle`, not abort the evaluator.
+    Err(Flow::Raise(Value::Constructor(constructors::C_MATCH, None)))
+}
+
+/// Like `eval_rules`, but for `handle`'s clauses matching against a raised
+/// value `raised`: unlike `case`, a `handle` whose clauses don't match
+/// doesn't raise `Match` in its place, it lets `raised` keep propagating,
+/// since the clauses are just filters over which exceptions this `handle`
+/// catches, not an exhaustive pattern match the program is asserting.
+fn eval_handler_rules<'ar>(
+    rules: &[Rule<'ar>],
+    raised: Value<'ar>,
+    env: &Env<'ar>,
+) -> EvalResult<'ar> {
+    for rule in rules {
+        if let Some(bindings) = match_pattern(&rule.pat, &raised) {
+            let mut env = env.clone();
+            env.extend(bindings);
+            return eval_expr(&rule.expr, &env);
+        }
+    }
+    Err(Flow::Raise(raised))
+}
+
+/// Attempt to match `value` against `pat`, returning the bindings it
+/// introduces on success.
+fn match_pattern<'ar>(pat: &Pat<'ar>, value: &Value<'ar>) -> Option<HashMap<Symbol, Value<'ar>>> {
+    match (pat.pat, value) {
+        (PatKind::Wild, _) => Some(HashMap::new()),
+        (PatKind::Var(sym), _) => {
+            let mut map = HashMap::new();
+            map.insert(*sym, value.clone());
+            Some(map)
+        }
+        (PatKind::Const(c1), Value::Const(c2)) if c1 == c2 => Some(HashMap::new()),
+        (PatKind::App(con, sub), Value::Constructor(vcon, varg)) if con == vcon => {
+            match (sub, varg) {
+                (Some(p), Some(v)) => match_pattern(p, v),
+                (None, None) => Some(HashMap::new()),
+                _ => None,
+            }
+        }
+        (PatKind::Record(rows), Value::Record(vrows)) => {
+            let mut map = HashMap::new();
+            for row in rows {
+                let (_, v) = vrows.iter().find(|(label, _)| *label == row.label)?;
+                map.extend(match_pattern(&row.data, v)?);
+            }
+            Some(map)
+        }
+        (PatKind::List(pats), _) => {
+            let mut map = HashMap::new();
+            let mut cur = value.clone();
+            for p in pats {
+                match cur {
+                    Value::Constructor(con, Some(arg)) if con == constructors::C_CONS => {
+                        if let Value::Record(mut rows) = *arg {
+                            let tail = rows.pop().unwrap().1;
+                            let head = rows.pop().unwrap().1;
+                            map.extend(match_pattern(p, &head)?);
+                            cur = tail;
+                        } else {
+                            return None;
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            match cur {
+                Value::Constructor(con, None) if con == constructors::C_NIL => Some(map),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// How many arguments `sym` needs to have seen before [`eval_primitive`]
+/// can run it. Unary operations (negation, printing) take one; everything
+/// else currently supported is a binary arithmetic or comparison op.
+fn primitive_arity(sym: Symbol) -> usize {
+    if sym == primitives::NEG || sym == primitives::PRINT {
+        1
+    } else {
+        2
+    }
+}
+
+/// Dispatch a fully-applied primitive on its (already evaluated) arguments.
+fn eval_primitive<'ar>(sym: Symbol, args: &[Value<'ar>]) -> EvalResult<'ar> {
+    fn as_int(v: &Value<'_>) -> i64 {
+        match v {
+            Value::Const(Const::Int(i)) => *i,
+            other => panic!("eval: primitive expected an int argument, got {:?}", other),
+        }
+    }
+    fn bool_value<'ar>(b: bool) -> Value<'ar> {
+        Value::Constructor(if b { constructors::C_TRUE } else { constructors::C_FALSE }, None)
+    }
+
+    let value = match (sym, args) {
+        (s, [a, b]) if s == primitives::ADD => Value::Const(Const::Int(as_int(a) + as_int(b))),
+        (s, [a, b]) if s == primitives::SUB => Value::Const(Const::Int(as_int(a) - as_int(b))),
+        (s, [a, b]) if s == primitives::MUL => Value::Const(Const::Int(as_int(a) * as_int(b))),
+        (s, [a, b]) if s == primitives::DIV => Value::Const(Const::Int(as_int(a) / as_int(b))),
+        (s, [a, b]) if s == primitives::EQ => bool_value(as_int(a) == as_int(b)),
+        (s, [a, b]) if s == primitives::LT => bool_value(as_int(a) < as_int(b)),
+        (s, [a]) if s == primitives::NEG => Value::Const(Const::Int(-as_int(a))),
+        (s, [a]) if s == primitives::PRINT => {
+            print!("{:?}", a);
+            Value::Record(Vec::new())
+        }
+        _ => panic!("eval: unimplemented primitive {:?} applied to {:?}", sym, args),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Expr as CoreExpr;
+    use sml_util::interner::Interner;
+    use sml_util::span::Span;
+
+    fn int(i: i64) -> Const {
+        Const::Int(i)
+    }
+
+    #[test]
+    fn recursive_fun_call_actually_computes() {
+        // `fun countdown n = case n of 0 => 0 | _ => countdown (n - 1)`,
+        // called on `2`. `Decl::Fun`'s `Lambda { arg: countdown, .. }`
+        // binds the *name* `countdown`, not a parameter: its body is
+        // itself `Lambda { arg: n, .. }`, the real one-parameter function.
+        // Applying `countdown` must run that inner lambda — and its
+        // recursive call must resolve `countdown` to the same closure, not
+        // drop the argument or find nothing — so the whole thing should
+        // actually reduce to `0`, not to some unapplied closure.
+        let mut interner = Interner::with_capacity(8);
+        let countdown = interner.intern("countdown");
+        let n = interner.intern("n");
+        let ty = crate::types::Type::Var(crate::types::TypeVar::unbound(0));
+
+        let zero_pat_kind = PatKind::Const(int(0));
+        let zero_pat = Pat::new(&zero_pat_kind, &ty, Span::default());
+        let zero_rhs_kind = ExprKind::Const(int(0));
+        let zero_rhs = CoreExpr::new(&zero_rhs_kind, &ty, Span::default());
+
+        let wild_pat_kind = PatKind::Wild;
+        let wild_pat = Pat::new(&wild_pat_kind, &ty, Span::default());
+
+        let n_var_kind = ExprKind::Var(n);
+        let n_var = CoreExpr::new(&n_var_kind, &ty, Span::default());
+        let one_kind = ExprKind::Const(int(1));
+        let one = CoreExpr::new(&one_kind, &ty, Span::default());
+        let sub_prim_kind = ExprKind::Primitive(primitives::SUB);
+        let sub_prim = CoreExpr::new(&sub_prim_kind, &ty, Span::default());
+        let sub_partial_kind = ExprKind::App(sub_prim, n_var);
+        let sub_partial = CoreExpr::new(&sub_partial_kind, &ty, Span::default());
+        let n_minus_one_kind = ExprKind::App(sub_partial, one);
+        let n_minus_one = CoreExpr::new(&n_minus_one_kind, &ty, Span::default());
+
+        let countdown_var_kind = ExprKind::Var(countdown);
+        let countdown_var = CoreExpr::new(&countdown_var_kind, &ty, Span::default());
+        let recurse_kind = ExprKind::App(countdown_var, n_minus_one);
+        let recurse = CoreExpr::new(&recurse_kind, &ty, Span::default());
+
+        let case_kind = ExprKind::Case(
+            n_var,
+            vec![Rule { pat: zero_pat, expr: zero_rhs }, Rule { pat: wild_pat, expr: recurse }],
+        );
+        let case = CoreExpr::new(&case_kind, &ty, Span::default());
+
+        let inner_kind = ExprKind::Lambda(Lambda { arg: n, ty: &ty, body: case });
+        let inner = CoreExpr::new(&inner_kind, &ty, Span::default());
+        let lambda = Lambda { arg: countdown, ty: &ty, body: inner };
+
+        let decl = Decl::Fun(Vec::new(), vec![lambda]);
+        let env = eval_decls(std::slice::from_ref(&decl), &Env::new()).expect("eval_decls");
+
+        let two_kind = ExprKind::Const(int(2));
+        let two = CoreExpr::new(&two_kind, &ty, Span::default());
+        let call_kind = ExprKind::App(countdown_var, two);
+        let call = CoreExpr::new(&call_kind, &ty, Span::default());
+
+        match eval_expr(&call, &env) {
+            Ok(Value::Const(Const::Int(0))) => {}
+            other => panic!("expected countdown(2) to reduce to 0, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn primitive_add_dispatches() {
+        let ty = crate::types::Type::Var(crate::types::TypeVar::unbound(0));
+        let lhs_kind = ExprKind::Const(int(1));
+        let rhs_kind = ExprKind::Const(int(2));
+        let lhs = CoreExpr::new(&lhs_kind, &ty, Span::default());
+        let rhs = CoreExpr::new(&rhs_kind, &ty, Span::default());
+
+        let prim_kind = ExprKind::Primitive(primitives::ADD);
+        let prim = CoreExpr::new(&prim_kind, &ty, Span::default());
+        let partial_kind = ExprKind::App(prim, lhs);
+        let partial = CoreExpr::new(&partial_kind, &ty, Span::default());
+        let full_kind = ExprKind::App(partial, rhs);
+        let full = CoreExpr::new(&full_kind, &ty, Span::default());
+
+        match eval_expr(&full, &Env::new()) {
+            Ok(Value::Const(Const::Int(3))) => {}
+            other => panic!("expected 1 + 2 = 3, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_exhaustive_case_raises_match_instead_of_panicking() {
+        let ty = crate::types::Type::Var(crate::types::TypeVar::unbound(0));
+        let scrutinee_kind = ExprKind::Const(int(0));
+        let scrutinee = CoreExpr::new(&scrutinee_kind, &ty, Span::default());
+
+        // A `case` with no rules at all can never match.
+        let case_kind = ExprKind::Case(scrutinee, Vec::new());
+        let case = CoreExpr::new(&case_kind, &ty, Span::default());
+
+        match eval_expr(&case, &Env::new()) {
+            Err(Flow::Raise(Value::Constructor(con, None))) => assert_eq!(con, constructors::C_MATCH),
+            other => panic!("expected a raised Match exception, got an Ok or a panic: {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn non_matching_val_pattern_raises_bind_instead_of_being_skipped() {
+        // `val 0 = 1` — the pattern can never match the right-hand side.
+        let ty = crate::types::Type::Var(crate::types::TypeVar::unbound(0));
+        let pat_kind = PatKind::Const(int(0));
+        let pat = Pat::new(&pat_kind, &ty, Span::default());
+        let rhs_kind = ExprKind::Const(int(1));
+        let rhs = CoreExpr::new(&rhs_kind, &ty, Span::default());
+
+        let decl = Decl::Val(Rule { pat, expr: rhs });
+
+        match eval_decls(std::slice::from_ref(&decl), &Env::new()) {
+            Err(Flow::Raise(Value::Constructor(con, None))) => assert_eq!(con, constructors::C_BIND),
+            other => panic!("expected a raised Bind exception, got an Ok or a panic: {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn handle_with_no_matching_clause_reraises_the_original_exception() {
+        // `(case 0 of) handle Bind => 0` — the body raises `Match` (an
+        // empty `case` never matches), and the handler's only clause
+        // matches `Bind`, not `Match`, so the *original* `Match` must
+        // keep propagating rather than being replaced by a fresh `Match`
+        // from treating the handler's clauses as a non-exhaustive case.
+        let ty = crate::types::Type::Var(crate::types::TypeVar::unbound(0));
+        let scrutinee_kind = ExprKind::Const(int(0));
+        let scrutinee = CoreExpr::new(&scrutinee_kind, &ty, Span::default());
+        let body_kind = ExprKind::Case(scrutinee, Vec::new());
+        let body = CoreExpr::new(&body_kind, &ty, Span::default());
+
+        let bind_pat_kind = PatKind::App(constructors::C_BIND, None);
+        let bind_pat = Pat::new(&bind_pat_kind, &ty, Span::default());
+        let zero_kind = ExprKind::Const(int(0));
+        let zero = CoreExpr::new(&zero_kind, &ty, Span::default());
+
+        let handle_kind = ExprKind::Handle(body, vec![Rule { pat: bind_pat, expr: zero }]);
+        let handle = CoreExpr::new(&handle_kind, &ty, Span::default());
+
+        match eval_expr(&handle, &Env::new()) {
+            Err(Flow::Raise(Value::Constructor(con, None))) => assert_eq!(con, constructors::C_MATCH),
+            other => panic!(
+                "expected the original Match exception to propagate, got an Ok or a panic: {:?}",
+                other.is_ok()
+            ),
+        }
+    }
+}
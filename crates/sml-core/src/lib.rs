@@ -9,7 +9,11 @@ pub mod arenas;
 pub mod builtin;
 pub mod check;
 pub mod elaborate;
+pub mod eval;
+pub mod serialize;
+pub mod subst;
 pub mod types;
+pub mod visit;
 use types::{Constructor, Scheme, Tycon, Type, TypeVar};
 
 pub use arenas::CoreArena;
@@ -0,0 +1,980 @@
+//! Binary (de)serialization of an elaborated program, for build caching.
+//!
+//! `Expr`/`Pat`/`Type` are arena references rather than owned trees, so we
+//! can't just derive `Serialize`/`Deserialize` on them directly: encoding
+//! flattens every node into an indexed table, deduplicated by the node's
+//! arena address, where a node's index is its position in that table
+//! (`ExprId`/`TypeId` are not used). Decoding works in two passes: first
+//! it reads every flat table, then it rebuilds the arena-backed pointer
+//! graph in dependency order, so that by the time a node is handed out,
+//! everything it points to already lives in the arena.
+//!
+//! Every table is written in post-order, so a node's children always have
+//! a lower index than the node itself; decoding can then allocate nodes in
+//! index order and translate a child index straight into the `&'ar`
+//! reference that was just produced for it, without a second fix-up pass.
+
+use crate::arenas::CoreArena;
+use crate::types::{Constructor, Tycon, Type, TypeVar};
+use crate::{Decl, Datatype, Expr, ExprKind, Lambda, Pat, PatKind, Row, Rule};
+use sml_frontend::ast::Const;
+use sml_util::interner::{Interner, Symbol};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// On-disk representation of a fully elaborated program: its top-level
+/// declarations, plus the side tables needed to reconstruct the types and
+/// symbols they reference.
+pub struct CoreImage {
+    pub decls: Vec<FlatDecl>,
+    pub exprs: Vec<FlatExpr>,
+    pub pats: Vec<FlatPat>,
+    pub types: Vec<FlatType>,
+    pub constructors: Vec<FlatConstructor>,
+    pub tycons: Vec<FlatTycon>,
+    pub symbols: Vec<String>,
+}
+
+/// A `Decl` with every arena reference replaced by its on-disk index (the
+/// node's position in the matching flat table), so the struct can be written out
+/// without needing a live arena.
+pub enum FlatDecl {
+    Datatype { tycon: u32, tyvars: Vec<usize>, constructors: Vec<(u32, Option<u32>)> },
+    Fun { tyvars: Vec<usize>, lambdas: Vec<FlatLambda> },
+    Val(FlatRule),
+    Exn(u32, Option<u32>),
+}
+
+pub struct FlatLambda {
+    pub arg: u32,
+    pub ty: u32,
+    pub body: u32,
+}
+
+pub struct FlatRule {
+    pub pat: u32,
+    pub expr: u32,
+}
+
+/// A flattened `ExprKind<'ar>` node, indexed into `CoreImage::exprs`.
+/// Carries its own type and symbol-table references since every `Expr`
+/// node owns a `ty` alongside its `ExprKind`.
+pub struct FlatExpr {
+    pub kind: FlatExprKind,
+    pub ty: u32,
+}
+
+pub enum FlatExprKind {
+    App(u32, u32),
+    Case(u32, Vec<FlatRule>),
+    Con(u32, Vec<u32>),
+    Const(FlatConst),
+    Handle(u32, Vec<FlatRule>),
+    Lambda(FlatLambda),
+    Let(Vec<FlatDecl>, u32),
+    List(Vec<u32>),
+    Primitive(u32),
+    Raise(u32),
+    Record(Vec<(u32, u32)>),
+    Seq(Vec<u32>),
+    Var(u32),
+}
+
+/// A flattened `PatKind<'ar>` node, indexed into `CoreImage::pats`.
+pub struct FlatPat {
+    pub kind: FlatPatKind,
+    pub ty: u32,
+}
+
+pub enum FlatPatKind {
+    App(u32, Option<u32>),
+    Const(FlatConst),
+    List(Vec<u32>),
+    Record(Vec<(u32, u32)>),
+    Var(u32),
+    Wild,
+}
+
+/// A flattened `Const`; `Const` isn't arena-allocated, but `String`
+/// payloads reference the symbol table like everything else.
+pub enum FlatConst {
+    Int(i64),
+    Char(char),
+    String(u32),
+    Unit,
+}
+
+/// A flattened `Tycon`.
+pub struct FlatTycon {
+    pub name: u32,
+    pub arity: usize,
+}
+
+/// A flattened `Constructor`.
+pub struct FlatConstructor {
+    pub name: u32,
+    pub tycon: u32,
+    pub tag: u32,
+}
+
+/// A flattened `Type<'ar>` node: children are referenced by `TypeId`
+/// rather than by `&'ar Type<'ar>`.
+pub enum FlatType {
+    Var(u32),
+    Con(u32, Vec<u32>),
+    Arrow(u32, u32),
+    Record(Vec<(u32, u32)>),
+}
+
+/// Encode `decls` (and the type/constructor/interner tables it
+/// references) into a compact binary blob.
+pub fn encode<W: Write>(decls: &[Decl<'_>], interner: &Interner, out: &mut W) -> io::Result<()> {
+    let symbols = interner.dump_strings();
+    let mut symbol_index = HashMap::with_capacity(symbols.len());
+    for (i, s) in symbols.iter().enumerate() {
+        symbol_index.insert(interner.intern(s), i as u32);
+    }
+
+    let mut enc = Encoder {
+        symbol_index,
+        type_index: HashMap::new(),
+        expr_index: HashMap::new(),
+        pat_index: HashMap::new(),
+        tycon_index: HashMap::new(),
+        constructor_index: HashMap::new(),
+        types: Vec::new(),
+        exprs: Vec::new(),
+        pats: Vec::new(),
+        constructors: Vec::new(),
+        tycons: Vec::new(),
+    };
+    let flat_decls: Vec<FlatDecl> = decls.iter().map(|d| enc.decl(d)).collect();
+
+    write_u32(out, symbols.len() as u32)?;
+    for s in &symbols {
+        write_string(out, s)?;
+    }
+
+    write_u32(out, enc.tycons.len() as u32)?;
+    for t in &enc.tycons {
+        write_u32(out, t.name)?;
+        write_u32(out, t.arity as u32)?;
+    }
+
+    write_u32(out, enc.constructors.len() as u32)?;
+    for c in &enc.constructors {
+        write_u32(out, c.name)?;
+        write_u32(out, c.tycon)?;
+        write_u32(out, c.tag)?;
+    }
+
+    write_u32(out, enc.types.len() as u32)?;
+    for ty in &enc.types {
+        write_flat_type(out, ty)?;
+    }
+
+    write_u32(out, enc.exprs.len() as u32)?;
+    for e in &enc.exprs {
+        write_flat_expr(out, e)?;
+    }
+
+    write_u32(out, enc.pats.len() as u32)?;
+    for p in &enc.pats {
+        write_flat_pat(out, p)?;
+    }
+
+    write_u32(out, flat_decls.len() as u32)?;
+    for d in &flat_decls {
+        write_flat_decl(out, d)?;
+    }
+
+    Ok(())
+}
+
+/// Decode a blob previously produced by [`encode`] back into a fresh
+/// [`CoreArena`] and the `Vec<Decl>` it backs.
+pub fn decode<'ar, R: Read>(input: &mut R, arena: &'ar CoreArena<'ar>) -> io::Result<Vec<Decl<'ar>>> {
+    let symbol_count = read_u32(input)?;
+    let mut symbols = Vec::with_capacity(symbol_count as usize);
+    for _ in 0..symbol_count {
+        symbols.push(arena.interner.intern(&read_string(input)?));
+    }
+
+    let tycon_count = read_u32(input)?;
+    let mut tycons = Vec::with_capacity(tycon_count as usize);
+    for _ in 0..tycon_count {
+        let name = symbols[read_u32(input)? as usize];
+        let arity = read_u32(input)? as usize;
+        tycons.push(Tycon::new(name, arity));
+    }
+
+    let constructor_count = read_u32(input)?;
+    let mut constructors = Vec::with_capacity(constructor_count as usize);
+    for _ in 0..constructor_count {
+        let name = symbols[read_u32(input)? as usize];
+        let tycon = tycons[read_u32(input)? as usize];
+        let tag = read_u32(input)?;
+        constructors.push(Constructor::new(name, tycon, tag));
+    }
+
+    // Pass 1: read every flat, index-addressed table.
+    let type_count = read_u32(input)?;
+    let mut flat_types = Vec::with_capacity(type_count as usize);
+    for _ in 0..type_count {
+        flat_types.push(read_flat_type(input)?);
+    }
+
+    let expr_count = read_u32(input)?;
+    let mut flat_exprs = Vec::with_capacity(expr_count as usize);
+    for _ in 0..expr_count {
+        flat_exprs.push(read_flat_expr(input)?);
+    }
+
+    let pat_count = read_u32(input)?;
+    let mut flat_pats = Vec::with_capacity(pat_count as usize);
+    for _ in 0..pat_count {
+        flat_pats.push(read_flat_pat(input)?);
+    }
+
+    let decl_count = read_u32(input)?;
+    let mut flat_decls = Vec::with_capacity(decl_count as usize);
+    for _ in 0..decl_count {
+        flat_decls.push(read_flat_decl(input)?);
+    }
+
+    // Pass 2: walk the flat tables in index order and allocate each node
+    // into `arena`, turning every `TypeId`/`ExprId` reference into the
+    // `&'ar` pointer the rebuilt node needs. Because we always allocate a
+    // referenced index before anything that points to it, every `&'ar`
+    // we hand out is already valid.
+    let mut dec = Decoder { arena, symbols, tycons, constructors, types: Vec::new(), exprs: Vec::new(), pats: Vec::new() };
+    for ty in &flat_types {
+        let t = dec.build_type(ty);
+        dec.types.push(t);
+    }
+    for p in &flat_pats {
+        let p = dec.build_pat(p);
+        dec.pats.push(p);
+    }
+    for e in &flat_exprs {
+        let e = dec.build_expr(e);
+        dec.exprs.push(e);
+    }
+
+    Ok(flat_decls.iter().map(|d| dec.build_decl(d)).collect())
+}
+
+struct Encoder<'ar> {
+    symbol_index: HashMap<Symbol, u32>,
+    type_index: HashMap<*const Type<'ar>, u32>,
+    expr_index: HashMap<*const ExprKind<'ar>, u32>,
+    pat_index: HashMap<*const PatKind<'ar>, u32>,
+    tycon_index: HashMap<Symbol, u32>,
+    constructor_index: HashMap<(Symbol, Symbol), u32>,
+    types: Vec<FlatType>,
+    exprs: Vec<FlatExpr>,
+    pats: Vec<FlatPat>,
+    constructors: Vec<FlatConstructor>,
+    tycons: Vec<FlatTycon>,
+}
+
+impl<'ar> Encoder<'ar> {
+    fn symbol(&mut self, sym: Symbol) -> u32 {
+        *self.symbol_index.entry(sym).or_insert_with(|| {
+            // Every symbol that reaches here should already have been
+            // seen in `interner.dump_strings()`; this only guards against
+            // a fresh symbol (e.g. a gensym) slipping through uninterned.
+            panic!("serialize: symbol {:?} missing from the dumped interner table", sym)
+        })
+    }
+
+    fn tycon(&mut self, t: &Tycon) -> u32 {
+        // Keyed by name rather than by pointer, like `ty`/`expr`/`pat`:
+        // `Tycon`s are plain values, not arena references, but every real
+        // tycon has a unique name, so that's enough to dedupe `int` (or
+        // any datatype) across however many types reference it.
+        if let Some(idx) = self.tycon_index.get(&t.name) {
+            return *idx;
+        }
+        let name = self.symbol(t.name);
+        self.tycons.push(FlatTycon { name, arity: t.arity });
+        let idx = (self.tycons.len() - 1) as u32;
+        self.tycon_index.insert(t.name, idx);
+        idx
+    }
+
+    fn constructor(&mut self, c: &Constructor) -> u32 {
+        let key = (c.tycon.name, c.name);
+        if let Some(idx) = self.constructor_index.get(&key) {
+            return *idx;
+        }
+        let name = self.symbol(c.name);
+        let tycon = self.tycon(&c.tycon);
+        self.constructors.push(FlatConstructor { name, tycon, tag: c.tag });
+        let idx = (self.constructors.len() - 1) as u32;
+        self.constructor_index.insert(key, idx);
+        idx
+    }
+
+    fn ty(&mut self, ty: &'ar Type<'ar>) -> u32 {
+        let key = ty as *const Type<'ar>;
+        if let Some(idx) = self.type_index.get(&key) {
+            return *idx;
+        }
+        // A `TypeVar` that unification has since linked to a concrete type
+        // carries a solved type, not a free metavariable; serialize that
+        // type directly rather than the `Var` indirection, or an
+        // elaborated program's solved types would come back from the
+        // cache as free variables. Only a genuinely still-unbound
+        // (generalized, scheme-level) variable is encoded as `FlatType::Var`.
+        if let Type::Var(tv) = ty {
+            if let Some(resolved) = tv.resolved() {
+                return self.ty(resolved);
+            }
+        }
+        let flat = match ty {
+            Type::Var(tv) => FlatType::Var(tv.id() as u32),
+            Type::Con(tycon, args) => {
+                let tycon = self.tycon(tycon);
+                let args = args.iter().map(|t| self.ty(t)).collect();
+                FlatType::Con(tycon, args)
+            }
+            Type::Arrow(dom, cod) => FlatType::Arrow(self.ty(dom), self.ty(cod)),
+            Type::Record(rows) => {
+                FlatType::Record(rows.iter().map(|r| (self.symbol(r.label), self.ty(r.data))).collect())
+            }
+        };
+        self.types.push(flat);
+        let idx = (self.types.len() - 1) as u32;
+        self.type_index.insert(key, idx);
+        idx
+    }
+
+    fn const_(&mut self, c: &Const) -> FlatConst {
+        match c {
+            Const::Int(i) => FlatConst::Int(*i as i64),
+            Const::Char(c) => FlatConst::Char(*c),
+            Const::String(s) => FlatConst::String(self.symbol(*s)),
+            Const::Unit => FlatConst::Unit,
+        }
+    }
+
+    fn expr(&mut self, e: &Expr<'ar>) -> u32 {
+        let key = e.expr as *const ExprKind<'ar>;
+        if let Some(idx) = self.expr_index.get(&key) {
+            return *idx;
+        }
+        let ty = self.ty(e.ty);
+        let kind = match e.expr {
+            ExprKind::App(f, arg) => FlatExprKind::App(self.expr(f), self.expr(arg)),
+            ExprKind::Case(scrutinee, rules) => {
+                FlatExprKind::Case(self.expr(scrutinee), rules.iter().map(|r| self.rule(r)).collect())
+            }
+            ExprKind::Con(con, args) => {
+                FlatExprKind::Con(self.constructor(con), args.iter().map(|t| self.ty(t)).collect())
+            }
+            ExprKind::Const(c) => FlatExprKind::Const(self.const_(c)),
+            ExprKind::Handle(body, rules) => {
+                FlatExprKind::Handle(self.expr(body), rules.iter().map(|r| self.rule(r)).collect())
+            }
+            ExprKind::Lambda(lam) => FlatExprKind::Lambda(self.lambda(lam)),
+            ExprKind::Let(decls, body) => {
+                FlatExprKind::Let(decls.iter().map(|d| self.decl(d)).collect(), self.expr(body))
+            }
+            ExprKind::List(exprs) => FlatExprKind::List(exprs.iter().map(|e| self.expr(e)).collect()),
+            ExprKind::Primitive(sym) => FlatExprKind::Primitive(self.symbol(*sym)),
+            ExprKind::Raise(e) => FlatExprKind::Raise(self.expr(e)),
+            ExprKind::Record(rows) => {
+                FlatExprKind::Record(rows.iter().map(|r| (self.symbol(r.label), self.expr(&r.data))).collect())
+            }
+            ExprKind::Seq(exprs) => FlatExprKind::Seq(exprs.iter().map(|e| self.expr(e)).collect()),
+            ExprKind::Var(sym) => FlatExprKind::Var(self.symbol(*sym)),
+        };
+        self.exprs.push(FlatExpr { kind, ty });
+        let idx = (self.exprs.len() - 1) as u32;
+        self.expr_index.insert(key, idx);
+        idx
+    }
+
+    fn pat(&mut self, p: &Pat<'ar>) -> u32 {
+        let key = p.pat as *const PatKind<'ar>;
+        if let Some(idx) = self.pat_index.get(&key) {
+            return *idx;
+        }
+        let ty = self.ty(p.ty);
+        let kind = match p.pat {
+            PatKind::App(con, sub) => FlatPatKind::App(self.constructor(con), sub.as_ref().map(|p| self.pat(p))),
+            PatKind::Const(c) => FlatPatKind::Const(self.const_(c)),
+            PatKind::List(pats) => FlatPatKind::List(pats.iter().map(|p| self.pat(p)).collect()),
+            PatKind::Record(rows) => {
+                FlatPatKind::Record(rows.iter().map(|r| (self.symbol(r.label), self.pat(&r.data))).collect())
+            }
+            PatKind::Var(sym) => FlatPatKind::Var(self.symbol(*sym)),
+            PatKind::Wild => FlatPatKind::Wild,
+        };
+        self.pats.push(FlatPat { kind, ty });
+        let idx = (self.pats.len() - 1) as u32;
+        self.pat_index.insert(key, idx);
+        idx
+    }
+
+    fn rule(&mut self, rule: &Rule<'ar>) -> FlatRule {
+        FlatRule { pat: self.pat(&rule.pat), expr: self.expr(&rule.expr) }
+    }
+
+    fn lambda(&mut self, lam: &Lambda<'ar>) -> FlatLambda {
+        FlatLambda { arg: self.symbol(lam.arg), ty: self.ty(lam.ty), body: self.expr(&lam.body) }
+    }
+
+    fn decl(&mut self, decl: &Decl<'ar>) -> FlatDecl {
+        match decl {
+            Decl::Datatype(dt) => FlatDecl::Datatype {
+                tycon: self.tycon(&dt.tycon),
+                tyvars: dt.tyvars.clone(),
+                constructors: dt
+                    .constructors
+                    .iter()
+                    .map(|(con, ty)| (self.constructor(con), ty.map(|t| self.ty(t))))
+                    .collect(),
+            },
+            Decl::Fun(tyvars, lambdas) => {
+                FlatDecl::Fun { tyvars: tyvars.clone(), lambdas: lambdas.iter().map(|l| self.lambda(l)).collect() }
+            }
+            Decl::Val(rule) => FlatDecl::Val(self.rule(rule)),
+            Decl::Exn(con, ty) => FlatDecl::Exn(self.constructor(con), ty.map(|t| self.ty(t))),
+        }
+    }
+}
+
+struct Decoder<'ar> {
+    arena: &'ar CoreArena<'ar>,
+    symbols: Vec<Symbol>,
+    tycons: Vec<Tycon>,
+    constructors: Vec<Constructor>,
+    types: Vec<&'ar Type<'ar>>,
+    exprs: Vec<Expr<'ar>>,
+    pats: Vec<Pat<'ar>>,
+}
+
+impl<'ar> Decoder<'ar> {
+    fn sym(&self, idx: u32) -> Symbol {
+        self.symbols[idx as usize]
+    }
+
+    fn const_(&self, c: &FlatConst) -> Const {
+        match c {
+            FlatConst::Int(i) => Const::Int(*i),
+            FlatConst::Char(c) => Const::Char(*c),
+            FlatConst::String(idx) => Const::String(self.sym(*idx)),
+            FlatConst::Unit => Const::Unit,
+        }
+    }
+
+    fn build_type(&self, ty: &FlatType) -> &'ar Type<'ar> {
+        let ty = match ty {
+            FlatType::Var(id) => Type::Var(TypeVar::unbound(*id as usize)),
+            FlatType::Con(tycon, args) => {
+                Type::Con(self.tycons[*tycon as usize].clone(), args.iter().map(|i| self.types[*i as usize]).collect())
+            }
+            FlatType::Arrow(dom, cod) => Type::Arrow(self.types[*dom as usize], self.types[*cod as usize]),
+            FlatType::Record(rows) => Type::Record(
+                rows.iter()
+                    .map(|(label, ty)| Row { label: self.sym(*label), data: self.types[*ty as usize], span: Default::default() })
+                    .collect(),
+            ),
+        };
+        self.arena.types.alloc(ty)
+    }
+
+    fn build_expr(&self, e: &FlatExpr) -> Expr<'ar> {
+        let ty = self.types[e.ty as usize];
+        let kind = match &e.kind {
+            FlatExprKind::App(f, arg) => ExprKind::App(self.exprs[*f as usize], self.exprs[*arg as usize]),
+            FlatExprKind::Case(scrutinee, rules) => {
+                ExprKind::Case(self.exprs[*scrutinee as usize], rules.iter().map(|r| self.build_rule(r)).collect())
+            }
+            FlatExprKind::Con(con, args) => {
+                ExprKind::Con(self.constructors[*con as usize].clone(), args.iter().map(|i| self.types[*i as usize]).collect())
+            }
+            FlatExprKind::Const(c) => ExprKind::Const(self.const_(c)),
+            FlatExprKind::Handle(body, rules) => {
+                ExprKind::Handle(self.exprs[*body as usize], rules.iter().map(|r| self.build_rule(r)).collect())
+            }
+            FlatExprKind::Lambda(lam) => ExprKind::Lambda(self.build_lambda(lam)),
+            FlatExprKind::Let(decls, body) => {
+                ExprKind::Let(decls.iter().map(|d| self.build_decl(d)).collect(), self.exprs[*body as usize])
+            }
+            FlatExprKind::List(exprs) => ExprKind::List(exprs.iter().map(|i| self.exprs[*i as usize]).collect()),
+            FlatExprKind::Primitive(sym) => ExprKind::Primitive(self.sym(*sym)),
+            FlatExprKind::Raise(e) => ExprKind::Raise(self.exprs[*e as usize]),
+            FlatExprKind::Record(rows) => ExprKind::Record(
+                rows.iter()
+                    .map(|(label, e)| Row { label: self.sym(*label), data: self.exprs[*e as usize], span: Default::default() })
+                    .collect(),
+            ),
+            FlatExprKind::Seq(exprs) => ExprKind::Seq(exprs.iter().map(|i| self.exprs[*i as usize]).collect()),
+            FlatExprKind::Var(sym) => ExprKind::Var(self.sym(*sym)),
+        };
+        Expr::new(self.arena.exprs.alloc(kind), ty, Default::default())
+    }
+
+    fn build_pat(&self, p: &FlatPat) -> Pat<'ar> {
+        let ty = self.types[p.ty as usize];
+        let kind = match &p.kind {
+            FlatPatKind::App(con, sub) => {
+                PatKind::App(self.constructors[*con as usize].clone(), sub.map(|i| self.pats[i as usize]))
+            }
+            FlatPatKind::Const(c) => PatKind::Const(self.const_(c)),
+            FlatPatKind::List(pats) => PatKind::List(pats.iter().map(|i| self.pats[*i as usize]).collect()),
+            FlatPatKind::Record(rows) => PatKind::Record(
+                rows.iter()
+                    .map(|(label, p)| Row { label: self.sym(*label), data: self.pats[*p as usize], span: Default::default() })
+                    .collect(),
+            ),
+            FlatPatKind::Var(sym) => PatKind::Var(self.sym(*sym)),
+            FlatPatKind::Wild => PatKind::Wild,
+        };
+        Pat::new(self.arena.pats.alloc(kind), ty, Default::default())
+    }
+
+    fn build_rule(&self, r: &FlatRule) -> Rule<'ar> {
+        Rule { pat: self.pats[r.pat as usize], expr: self.exprs[r.expr as usize] }
+    }
+
+    fn build_lambda(&self, l: &FlatLambda) -> Lambda<'ar> {
+        Lambda { arg: self.sym(l.arg), ty: self.types[l.ty as usize], body: self.exprs[l.body as usize] }
+    }
+
+    fn build_decl(&self, d: &FlatDecl) -> Decl<'ar> {
+        match d {
+            FlatDecl::Datatype { tycon, tyvars, constructors } => Decl::Datatype(Datatype {
+                tycon: self.tycons[*tycon as usize].clone(),
+                tyvars: tyvars.clone(),
+                constructors: constructors
+                    .iter()
+                    .map(|(con, ty)| (self.constructors[*con as usize].clone(), ty.map(|i| self.types[i as usize])))
+                    .collect(),
+            }),
+            FlatDecl::Fun { tyvars, lambdas } => {
+                Decl::Fun(tyvars.clone(), lambdas.iter().map(|l| self.build_lambda(l)).collect())
+            }
+            FlatDecl::Val(rule) => Decl::Val(self.build_rule(rule)),
+            FlatDecl::Exn(con, ty) => Decl::Exn(self.constructors[*con as usize].clone(), ty.map(|i| self.types[i as usize])),
+        }
+    }
+}
+
+fn write_flat_type<W: Write>(out: &mut W, ty: &FlatType) -> io::Result<()> {
+    match ty {
+        FlatType::Var(id) => {
+            write_u8(out, 0)?;
+            write_u32(out, *id)
+        }
+        FlatType::Con(tycon, args) => {
+            write_u8(out, 1)?;
+            write_u32(out, *tycon)?;
+            write_u32_vec(out, args)
+        }
+        FlatType::Arrow(dom, cod) => {
+            write_u8(out, 2)?;
+            write_u32(out, *dom)?;
+            write_u32(out, *cod)
+        }
+        FlatType::Record(rows) => {
+            write_u8(out, 3)?;
+            write_u32(out, rows.len() as u32)?;
+            for (label, ty) in rows {
+                write_u32(out, *label)?;
+                write_u32(out, *ty)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn read_flat_type<R: Read>(input: &mut R) -> io::Result<FlatType> {
+    match read_u8(input)? {
+        0 => Ok(FlatType::Var(read_u32(input)?)),
+        1 => Ok(FlatType::Con(read_u32(input)?, read_u32_vec(input)?)),
+        2 => Ok(FlatType::Arrow(read_u32(input)?, read_u32(input)?)),
+        3 => {
+            let len = read_u32(input)?;
+            let mut rows = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                rows.push((read_u32(input)?, read_u32(input)?));
+            }
+            Ok(FlatType::Record(rows))
+        }
+        tag => Err(invalid_data(format!("unknown FlatType tag {}", tag))),
+    }
+}
+
+fn write_flat_const<W: Write>(out: &mut W, c: &FlatConst) -> io::Result<()> {
+    match c {
+        FlatConst::Int(i) => {
+            write_u8(out, 0)?;
+            out.write_all(&i.to_le_bytes())
+        }
+        FlatConst::Char(c) => {
+            write_u8(out, 1)?;
+            write_u32(out, *c as u32)
+        }
+        FlatConst::String(idx) => {
+            write_u8(out, 2)?;
+            write_u32(out, *idx)
+        }
+        FlatConst::Unit => write_u8(out, 3),
+    }
+}
+
+fn read_flat_const<R: Read>(input: &mut R) -> io::Result<FlatConst> {
+    match read_u8(input)? {
+        0 => {
+            let mut buf = [0u8; 8];
+            input.read_exact(&mut buf)?;
+            Ok(FlatConst::Int(i64::from_le_bytes(buf)))
+        }
+        1 => {
+            let c = read_u32(input)?;
+            char::from_u32(c).map(FlatConst::Char).ok_or_else(|| invalid_data("invalid char constant"))
+        }
+        2 => Ok(FlatConst::String(read_u32(input)?)),
+        3 => Ok(FlatConst::Unit),
+        tag => Err(invalid_data(format!("unknown FlatConst tag {}", tag))),
+    }
+}
+
+fn write_flat_rule<W: Write>(out: &mut W, r: &FlatRule) -> io::Result<()> {
+    write_u32(out, r.pat)?;
+    write_u32(out, r.expr)
+}
+
+fn read_flat_rule<R: Read>(input: &mut R) -> io::Result<FlatRule> {
+    Ok(FlatRule { pat: read_u32(input)?, expr: read_u32(input)? })
+}
+
+fn write_flat_lambda<W: Write>(out: &mut W, l: &FlatLambda) -> io::Result<()> {
+    write_u32(out, l.arg)?;
+    write_u32(out, l.ty)?;
+    write_u32(out, l.body)
+}
+
+fn read_flat_lambda<R: Read>(input: &mut R) -> io::Result<FlatLambda> {
+    Ok(FlatLambda { arg: read_u32(input)?, ty: read_u32(input)?, body: read_u32(input)? })
+}
+
+fn write_flat_expr<W: Write>(out: &mut W, e: &FlatExpr) -> io::Result<()> {
+    write_u32(out, e.ty)?;
+    match &e.kind {
+        FlatExprKind::App(f, arg) => {
+            write_u8(out, 0)?;
+            write_u32(out, *f)?;
+            write_u32(out, *arg)
+        }
+        FlatExprKind::Case(scrutinee, rules) => {
+            write_u8(out, 1)?;
+            write_u32(out, *scrutinee)?;
+            write_u32(out, rules.len() as u32)?;
+            rules.iter().try_for_each(|r| write_flat_rule(out, r))
+        }
+        FlatExprKind::Con(con, args) => {
+            write_u8(out, 2)?;
+            write_u32(out, *con)?;
+            write_u32_vec(out, args)
+        }
+        FlatExprKind::Const(c) => {
+            write_u8(out, 3)?;
+            write_flat_const(out, c)
+        }
+        FlatExprKind::Handle(body, rules) => {
+            write_u8(out, 4)?;
+            write_u32(out, *body)?;
+            write_u32(out, rules.len() as u32)?;
+            rules.iter().try_for_each(|r| write_flat_rule(out, r))
+        }
+        FlatExprKind::Lambda(lam) => {
+            write_u8(out, 5)?;
+            write_flat_lambda(out, lam)
+        }
+        FlatExprKind::Let(decls, body) => {
+            write_u8(out, 6)?;
+            write_u32(out, decls.len() as u32)?;
+            decls.iter().try_for_each(|d| write_flat_decl(out, d))?;
+            write_u32(out, *body)
+        }
+        FlatExprKind::List(exprs) => {
+            write_u8(out, 7)?;
+            write_u32_vec(out, exprs)
+        }
+        FlatExprKind::Primitive(sym) => {
+            write_u8(out, 8)?;
+            write_u32(out, *sym)
+        }
+        FlatExprKind::Raise(e) => {
+            write_u8(out, 9)?;
+            write_u32(out, *e)
+        }
+        FlatExprKind::Record(rows) => {
+            write_u8(out, 10)?;
+            write_u32(out, rows.len() as u32)?;
+            for (label, e) in rows {
+                write_u32(out, *label)?;
+                write_u32(out, *e)?;
+            }
+            Ok(())
+        }
+        FlatExprKind::Seq(exprs) => {
+            write_u8(out, 11)?;
+            write_u32_vec(out, exprs)
+        }
+        FlatExprKind::Var(sym) => {
+            write_u8(out, 12)?;
+            write_u32(out, *sym)
+        }
+    }
+}
+
+fn read_flat_expr<R: Read>(input: &mut R) -> io::Result<FlatExpr> {
+    let ty = read_u32(input)?;
+    let kind = match read_u8(input)? {
+        0 => FlatExprKind::App(read_u32(input)?, read_u32(input)?),
+        1 => {
+            let scrutinee = read_u32(input)?;
+            let len = read_u32(input)?;
+            let mut rules = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                rules.push(read_flat_rule(input)?);
+            }
+            FlatExprKind::Case(scrutinee, rules)
+        }
+        2 => FlatExprKind::Con(read_u32(input)?, read_u32_vec(input)?),
+        3 => FlatExprKind::Const(read_flat_const(input)?),
+        4 => {
+            let body = read_u32(input)?;
+            let len = read_u32(input)?;
+            let mut rules = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                rules.push(read_flat_rule(input)?);
+            }
+            FlatExprKind::Handle(body, rules)
+        }
+        5 => FlatExprKind::Lambda(read_flat_lambda(input)?),
+        6 => {
+            let len = read_u32(input)?;
+            let mut decls = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                decls.push(read_flat_decl(input)?);
+            }
+            let body = read_u32(input)?;
+            FlatExprKind::Let(decls, body)
+        }
+        7 => FlatExprKind::List(read_u32_vec(input)?),
+        8 => FlatExprKind::Primitive(read_u32(input)?),
+        9 => FlatExprKind::Raise(read_u32(input)?),
+        10 => {
+            let len = read_u32(input)?;
+            let mut rows = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                rows.push((read_u32(input)?, read_u32(input)?));
+            }
+            FlatExprKind::Record(rows)
+        }
+        11 => FlatExprKind::Seq(read_u32_vec(input)?),
+        12 => FlatExprKind::Var(read_u32(input)?),
+        tag => return Err(invalid_data(format!("unknown FlatExprKind tag {}", tag))),
+    };
+    Ok(FlatExpr { kind, ty })
+}
+
+fn write_flat_pat<W: Write>(out: &mut W, p: &FlatPat) -> io::Result<()> {
+    write_u32(out, p.ty)?;
+    match &p.kind {
+        FlatPatKind::App(con, sub) => {
+            write_u8(out, 0)?;
+            write_u32(out, *con)?;
+            write_option_u32(out, *sub)
+        }
+        FlatPatKind::Const(c) => {
+            write_u8(out, 1)?;
+            write_flat_const(out, c)
+        }
+        FlatPatKind::List(pats) => {
+            write_u8(out, 2)?;
+            write_u32_vec(out, pats)
+        }
+        FlatPatKind::Record(rows) => {
+            write_u8(out, 3)?;
+            write_u32(out, rows.len() as u32)?;
+            for (label, p) in rows {
+                write_u32(out, *label)?;
+                write_u32(out, *p)?;
+            }
+            Ok(())
+        }
+        FlatPatKind::Var(sym) => {
+            write_u8(out, 4)?;
+            write_u32(out, *sym)
+        }
+        FlatPatKind::Wild => write_u8(out, 5),
+    }
+}
+
+fn read_flat_pat<R: Read>(input: &mut R) -> io::Result<FlatPat> {
+    let ty = read_u32(input)?;
+    let kind = match read_u8(input)? {
+        0 => FlatPatKind::App(read_u32(input)?, read_option_u32(input)?),
+        1 => FlatPatKind::Const(read_flat_const(input)?),
+        2 => FlatPatKind::List(read_u32_vec(input)?),
+        3 => {
+            let len = read_u32(input)?;
+            let mut rows = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                rows.push((read_u32(input)?, read_u32(input)?));
+            }
+            FlatPatKind::Record(rows)
+        }
+        4 => FlatPatKind::Var(read_u32(input)?),
+        5 => FlatPatKind::Wild,
+        tag => return Err(invalid_data(format!("unknown FlatPatKind tag {}", tag))),
+    };
+    Ok(FlatPat { kind, ty })
+}
+
+fn write_flat_decl<W: Write>(out: &mut W, d: &FlatDecl) -> io::Result<()> {
+    match d {
+        FlatDecl::Datatype { tycon, tyvars, constructors } => {
+            write_u8(out, 0)?;
+            write_u32(out, *tycon)?;
+            write_usize_vec(out, tyvars)?;
+            write_u32(out, constructors.len() as u32)?;
+            for (con, ty) in constructors {
+                write_u32(out, *con)?;
+                write_option_u32(out, *ty)?;
+            }
+            Ok(())
+        }
+        FlatDecl::Fun { tyvars, lambdas } => {
+            write_u8(out, 1)?;
+            write_usize_vec(out, tyvars)?;
+            write_u32(out, lambdas.len() as u32)?;
+            lambdas.iter().try_for_each(|l| write_flat_lambda(out, l))
+        }
+        FlatDecl::Val(rule) => {
+            write_u8(out, 2)?;
+            write_flat_rule(out, rule)
+        }
+        FlatDecl::Exn(con, ty) => {
+            write_u8(out, 3)?;
+            write_u32(out, *con)?;
+            write_option_u32(out, *ty)
+        }
+    }
+}
+
+fn read_flat_decl<R: Read>(input: &mut R) -> io::Result<FlatDecl> {
+    match read_u8(input)? {
+        0 => {
+            let tycon = read_u32(input)?;
+            let tyvars = read_usize_vec(input)?;
+            let len = read_u32(input)?;
+            let mut constructors = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                constructors.push((read_u32(input)?, read_option_u32(input)?));
+            }
+            Ok(FlatDecl::Datatype { tycon, tyvars, constructors })
+        }
+        1 => {
+            let tyvars = read_usize_vec(input)?;
+            let len = read_u32(input)?;
+            let mut lambdas = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                lambdas.push(read_flat_lambda(input)?);
+            }
+            Ok(FlatDecl::Fun { tyvars, lambdas })
+        }
+        2 => Ok(FlatDecl::Val(read_flat_rule(input)?)),
+        3 => Ok(FlatDecl::Exn(read_u32(input)?, read_option_u32(input)?)),
+        tag => Err(invalid_data(format!("unknown FlatDecl tag {}", tag))),
+    }
+}
+
+fn write_u32<W: Write>(out: &mut W, val: u32) -> io::Result<()> {
+    out.write_all(&val.to_le_bytes())
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn write_u8<W: Write>(out: &mut W, val: u8) -> io::Result<()> {
+    out.write_all(&[val])
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn write_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    write_u32(out, s.len() as u32)?;
+    out.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(input: &mut R) -> io::Result<String> {
+    let len = read_u32(input)? as usize;
+    let mut buf = vec![0u8; len];
+    input.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn write_u32_vec<W: Write>(out: &mut W, vals: &[u32]) -> io::Result<()> {
+    write_u32(out, vals.len() as u32)?;
+    vals.iter().try_for_each(|v| write_u32(out, *v))
+}
+
+fn read_u32_vec<R: Read>(input: &mut R) -> io::Result<Vec<u32>> {
+    let len = read_u32(input)?;
+    let mut out = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        out.push(read_u32(input)?);
+    }
+    Ok(out)
+}
+
+fn write_usize_vec<W: Write>(out: &mut W, vals: &[usize]) -> io::Result<()> {
+    write_u32(out, vals.len() as u32)?;
+    vals.iter().try_for_each(|v| write_u32(out, *v as u32))
+}
+
+fn read_usize_vec<R: Read>(input: &mut R) -> io::Result<Vec<usize>> {
+    let len = read_u32(input)?;
+    let mut out = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        out.push(read_u32(input)? as usize);
+    }
+    Ok(out)
+}
+
+fn write_option_u32<W: Write>(out: &mut W, val: Option<u32>) -> io::Result<()> {
+    match val {
+        Some(v) => {
+            write_u8(out, 1)?;
+            write_u32(out, v)
+        }
+        None => write_u8(out, 0),
+    }
+}
+
+fn read_option_u32<R: Read>(input: &mut R) -> io::Result<Option<u32>> {
+    match read_u8(input)? {
+        0 => Ok(None),
+        _ => Ok(Some(read_u32(input)?)),
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
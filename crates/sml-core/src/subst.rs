@@ -0,0 +1,487 @@
+//! Capture-avoiding substitution and beta-reduction over the Core AST.
+//!
+//! This is the foundation for an inlining/constant-propagation pass: once
+//! we can soundly substitute a value for a variable, `beta_reduce` turns
+//! `(fn x => body) v` directly into `body[v/x]` without re-elaborating
+//! anything.
+
+use crate::arenas::CoreArena;
+use crate::visit::FreeVars;
+use crate::{Decl, Expr, ExprKind, Lambda, PatKind, Rule};
+use sml_util::interner::Symbol;
+
+/// Substitute `replacement` for every free occurrence of `var` in `expr`,
+/// renaming binders that would otherwise capture a variable free in
+/// `replacement`.
+pub fn substitute<'ar>(
+    expr: Expr<'ar>,
+    var: Symbol,
+    replacement: Expr<'ar>,
+    arena: &CoreArena<'ar>,
+) -> Expr<'ar> {
+    let free_in_replacement = FreeVars::of(&replacement);
+    subst_expr(expr, var, replacement, &free_in_replacement, arena)
+}
+
+/// Rewrite `App(Lambda { arg, body, .. }, value)` into `body[value/arg]`.
+/// Any other expression is returned unchanged.
+pub fn beta_reduce<'ar>(expr: Expr<'ar>, arena: &CoreArena<'ar>) -> Expr<'ar> {
+    match expr.expr {
+        ExprKind::App(f, value) => {
+            if let ExprKind::Lambda(lambda) = f.expr {
+                return substitute(lambda.body, lambda.arg, *value, arena);
+            }
+            expr
+        }
+        _ => expr,
+    }
+}
+
+fn subst_expr<'ar>(
+    expr: Expr<'ar>,
+    var: Symbol,
+    replacement: Expr<'ar>,
+    free_in_replacement: &std::collections::HashSet<Symbol>,
+    arena: &CoreArena<'ar>,
+) -> Expr<'ar> {
+    let kind = match expr.expr {
+        ExprKind::Var(sym) if *sym == var => return replacement,
+        ExprKind::Var(_) | ExprKind::Const(_) | ExprKind::Con(_, _) | ExprKind::Primitive(_) => {
+            return expr
+        }
+        ExprKind::App(e1, e2) => ExprKind::App(
+            subst_expr(*e1, var, replacement, free_in_replacement, arena),
+            subst_expr(*e2, var, replacement, free_in_replacement, arena),
+        ),
+        ExprKind::Raise(e) => ExprKind::Raise(subst_expr(*e, var, replacement, free_in_replacement, arena)),
+        ExprKind::List(exprs) => ExprKind::List(
+            exprs
+                .iter()
+                .map(|e| subst_expr(*e, var, replacement, free_in_replacement, arena))
+                .collect(),
+        ),
+        ExprKind::Seq(exprs) => ExprKind::Seq(
+            exprs
+                .iter()
+                .map(|e| subst_expr(*e, var, replacement, free_in_replacement, arena))
+                .collect(),
+        ),
+        ExprKind::Record(rows) => ExprKind::Record(
+            rows.iter()
+                .map(|row| row.fmap(|e| subst_expr(*e, var, replacement, free_in_replacement, arena)))
+                .collect(),
+        ),
+        ExprKind::Lambda(lambda) => {
+            if lambda.arg == var {
+                return expr;
+            }
+            ExprKind::Lambda(subst_lambda(lambda.clone(), var, replacement, free_in_replacement, arena))
+        }
+        ExprKind::Case(scrutinee, rules) => ExprKind::Case(
+            subst_expr(*scrutinee, var, replacement, free_in_replacement, arena),
+            rules
+                .iter()
+                .map(|rule| subst_rule(rule.clone(), var, replacement, free_in_replacement, arena))
+                .collect(),
+        ),
+        ExprKind::Handle(body, rules) => ExprKind::Handle(
+            subst_expr(*body, var, replacement, free_in_replacement, arena),
+            rules
+                .iter()
+                .map(|rule| subst_rule(rule.clone(), var, replacement, free_in_replacement, arena))
+                .collect(),
+        ),
+        ExprKind::Let(decls, body) => {
+            // `var` is shadowed by any binder among `decls` that rebinds
+            // it; we still need to substitute through the earlier decls
+            // and, unless shadowed, through the body.
+            let mut shadowed = false;
+            let mut new_decls = Vec::with_capacity(decls.len());
+            let mut decls: Vec<Decl<'ar>> = decls.clone();
+            let mut body = *body;
+            for i in 0..decls.len() {
+                if shadowed {
+                    new_decls.push(decls[i].clone());
+                    continue;
+                }
+                let (new_decl, binds_var, renames) =
+                    subst_decl(decls[i].clone(), var, replacement, free_in_replacement, arena);
+
+                // `subst_decl` may have renamed one of *this* decl's own
+                // binders to dodge a collision with something free in
+                // `replacement` (e.g. a `Decl::Fun` group's own name, via
+                // the same sibling-sweep it does internally). That
+                // binder's scope isn't just this decl — it's every
+                // sibling decl that follows and the `Let`'s own body — so
+                // the rename has to propagate there too, stopping only if
+                // a later sibling rebinds the same old name first.
+                for (from, to) in renames {
+                    let mut reshadowed = false;
+                    for later in decls[(i + 1)..].iter_mut() {
+                        if reshadowed {
+                            break;
+                        }
+                        let (renamed, rebinds) = rename_decl(later.clone(), from, to, arena);
+                        *later = renamed;
+                        reshadowed = rebinds;
+                    }
+                    if !reshadowed {
+                        body = rename(body, from, to, arena);
+                    }
+                }
+
+                new_decls.push(new_decl);
+                shadowed |= binds_var;
+            }
+            let new_body = if shadowed {
+                body
+            } else {
+                subst_expr(body, var, replacement, free_in_replacement, arena)
+            };
+            ExprKind::Let(new_decls, new_body)
+        }
+    };
+    Expr::new(arena.exprs.alloc(kind), expr.ty, expr.span)
+}
+
+fn subst_lambda<'ar>(
+    mut lambda: Lambda<'ar>,
+    var: Symbol,
+    replacement: Expr<'ar>,
+    free_in_replacement: &std::collections::HashSet<Symbol>,
+    arena: &CoreArena<'ar>,
+) -> Lambda<'ar> {
+    if free_in_replacement.contains(&lambda.arg) {
+        let fresh = arena.interner.fresh();
+        lambda.body = rename(lambda.body, lambda.arg, fresh, arena);
+        lambda.arg = fresh;
+    }
+    lambda.body = subst_expr(lambda.body, var, replacement, free_in_replacement, arena);
+    lambda
+}
+
+fn subst_rule<'ar>(
+    mut rule: Rule<'ar>,
+    var: Symbol,
+    replacement: Expr<'ar>,
+    free_in_replacement: &std::collections::HashSet<Symbol>,
+    arena: &CoreArena<'ar>,
+) -> Rule<'ar> {
+    let bound = pat_vars(&rule.pat);
+    if bound.contains(&var) {
+        return rule;
+    }
+    for sym in &bound {
+        if free_in_replacement.contains(sym) {
+            let fresh = arena.interner.fresh();
+            rule.expr = rename(rule.expr, *sym, fresh, arena);
+            rule.pat = rename_pat(rule.pat, *sym, fresh, arena);
+        }
+    }
+    rule.expr = subst_expr(rule.expr, var, replacement, free_in_replacement, arena);
+    rule
+}
+
+/// Substitute through a `Decl`, returning the rewritten declaration,
+/// whether it rebinds `var` (in which case later siblings/the body are
+/// left untouched by the caller), and any (old, new) renames this decl's
+/// own binders underwent to dodge capture. A rename's binder is visible
+/// to every sibling decl that follows and to the enclosing `Let`'s body,
+/// just as much as it's visible within this decl — the caller is
+/// responsible for sweeping it through both via [`rename_decl`]/[`rename`].
+fn subst_decl<'ar>(
+    decl: Decl<'ar>,
+    var: Symbol,
+    replacement: Expr<'ar>,
+    free_in_replacement: &std::collections::HashSet<Symbol>,
+    arena: &CoreArena<'ar>,
+) -> (Decl<'ar>, bool, Vec<(Symbol, Symbol)>) {
+    match decl {
+        Decl::Val(mut rule) => {
+            // Unlike a Case/Handle rule, `val pat = expr` evaluates `expr`
+            // in the *enclosing* scope, so `subst_rule`'s "don't touch
+            // `expr` if the pattern already shadows `var`" guard doesn't
+            // apply here: `expr` always gets substituted. Only the
+            // pattern's own bound names shadow `var` for whatever follows
+            // this decl (handled by the caller via `binds_var`).
+            let bound = pat_vars(&rule.pat);
+            let mut renames = Vec::new();
+            for sym in &bound {
+                if free_in_replacement.contains(sym) {
+                    let fresh = arena.interner.fresh();
+                    rule.pat = rename_pat(rule.pat, *sym, fresh, arena);
+                    renames.push((*sym, fresh));
+                }
+            }
+            let binds_var = bound.contains(&var);
+            rule.expr = subst_expr(rule.expr, var, replacement, free_in_replacement, arena);
+            (Decl::Val(rule), binds_var, renames)
+        }
+        Decl::Fun(tyvars, lambdas) => {
+            let binds_var = lambdas.iter().any(|l| l.arg == var);
+
+            // Unlike a real parameter (scoped to the one lambda that
+            // binds it), each `lambda.arg` here is a mutually-recursive
+            // function's own name, visible to every sibling's body. If
+            // one collides with something free in `replacement`, the
+            // rename has to sweep every sibling — not just the lambda
+            // that owns the name — or a sibling calling it by the old
+            // name would be left referencing a symbol nothing binds
+            // anymore. Do that sweep before handing each lambda to
+            // `subst_lambda`, which only knows how to rename a single
+            // lambda's own (real) parameter.
+            let mut lambdas = lambdas;
+            let colliding: Vec<Symbol> =
+                lambdas.iter().map(|l| l.arg).filter(|a| free_in_replacement.contains(a)).collect();
+            let mut renames = Vec::new();
+            for old in colliding {
+                let fresh = arena.interner.fresh();
+                lambdas = lambdas
+                    .into_iter()
+                    .map(|mut l| {
+                        l.body = rename(l.body, old, fresh, arena);
+                        if l.arg == old {
+                            l.arg = fresh;
+                        }
+                        l
+                    })
+                    .collect();
+                renames.push((old, fresh));
+            }
+
+            if binds_var {
+                (Decl::Fun(tyvars, lambdas), true, renames)
+            } else {
+                let lambdas = lambdas
+                    .into_iter()
+                    .map(|l| subst_lambda(l, var, replacement, free_in_replacement, arena))
+                    .collect();
+                (Decl::Fun(tyvars, lambdas), false, renames)
+            }
+        }
+        other => (other, false, Vec::new()),
+    }
+}
+
+/// Alpha-rename binder(s) this same `decl` variant renames internally
+/// (see `subst_decl`'s sibling-sweep), applied to a *different* decl that
+/// merely references `from` — used to sweep a rename made in one `Let`
+/// binding through the sibling decls that follow it. Returns whether
+/// `decl` itself rebinds `from`, which tells the caller to stop sweeping
+/// any further (a rebinding shadows `from` for everything after it).
+fn rename_decl<'ar>(decl: Decl<'ar>, from: Symbol, to: Symbol, arena: &CoreArena<'ar>) -> (Decl<'ar>, bool) {
+    match decl {
+        Decl::Val(mut rule) => {
+            let rebinds = pat_vars(&rule.pat).contains(&from);
+            rule.expr = rename(rule.expr, from, to, arena);
+            (Decl::Val(rule), rebinds)
+        }
+        Decl::Fun(tyvars, lambdas) => {
+            let rebinds = lambdas.iter().any(|l| l.arg == from);
+            // The group's own names are in scope in every sibling's body,
+            // so a group that rebinds `from` shadows it there as well.
+            if rebinds {
+                return (Decl::Fun(tyvars, lambdas), true);
+            }
+            let lambdas = lambdas
+                .into_iter()
+                .map(|mut l| {
+                    l.body = rename(l.body, from, to, arena);
+                    l
+                })
+                .collect();
+            (Decl::Fun(tyvars, lambdas), rebinds)
+        }
+        other => (other, false),
+    }
+}
+
+fn pat_vars<'ar>(pat: &crate::Pat<'ar>) -> Vec<Symbol> {
+    let mut out = Vec::new();
+    collect_pat_vars(pat, &mut out);
+    out
+}
+
+fn collect_pat_vars<'ar>(pat: &crate::Pat<'ar>, out: &mut Vec<Symbol>) {
+    match pat.pat {
+        PatKind::Var(sym) => out.push(*sym),
+        PatKind::App(_, Some(sub)) => collect_pat_vars(sub, out),
+        PatKind::Record(rows) => {
+            for row in rows {
+                collect_pat_vars(&row.data, out);
+            }
+        }
+        PatKind::List(pats) => {
+            for p in pats {
+                collect_pat_vars(p, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Alpha-rename every bound occurrence of `from` to `to` within `expr`.
+/// Used only to dodge variable capture immediately before a substitution.
+fn rename<'ar>(expr: Expr<'ar>, from: Symbol, to: Symbol, arena: &CoreArena<'ar>) -> Expr<'ar> {
+    let fresh_var = Expr::new(arena.exprs.alloc(ExprKind::Var(to)), expr.ty, expr.span);
+    subst_expr(expr, from, fresh_var, &std::iter::once(to).collect(), arena)
+}
+
+fn rename_pat<'ar>(pat: crate::Pat<'ar>, from: Symbol, to: Symbol, arena: &CoreArena<'ar>) -> crate::Pat<'ar> {
+    let kind = match pat.pat {
+        PatKind::Var(sym) if *sym == from => PatKind::Var(to),
+        PatKind::App(con, Some(sub)) => {
+            PatKind::App(con.clone(), Some(rename_pat(sub.clone(), from, to, arena)))
+        }
+        PatKind::Record(rows) => PatKind::Record(
+            rows.iter()
+                .map(|row| row.fmap(|p| rename_pat(p.clone(), from, to, arena)))
+                .collect(),
+        ),
+        PatKind::List(pats) => PatKind::List(
+            pats.iter()
+                .map(|p| rename_pat(p.clone(), from, to, arena))
+                .collect(),
+        ),
+        other => other.clone(),
+    };
+    crate::Pat::new(arena.pats.alloc(kind), pat.ty, pat.span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Type, TypeVar};
+    use crate::Rule;
+    use sml_util::span::Span;
+
+    #[test]
+    fn val_rhs_substitutes_even_though_the_pattern_rebinds_the_same_name() {
+        // `let val x = x in x end`, substituting a constant for the outer
+        // `x`: the right-hand side's `x` refers to the outer binding and
+        // must be substituted, even though this decl's pattern happens to
+        // rebind the name `x` for what follows.
+        let arena = CoreArena::default();
+        let x = arena.interner.intern("x");
+        let ty: &_ = arena.types.alloc(Type::Var(TypeVar::unbound(0)));
+
+        let rhs = Expr::new(arena.exprs.alloc(ExprKind::Var(x)), ty, Span::default());
+        let pat = crate::Pat::new(arena.pats.alloc(PatKind::Var(x)), ty, Span::default());
+        let decl = Decl::Val(Rule { pat, expr: rhs });
+
+        let body = Expr::new(arena.exprs.alloc(ExprKind::Var(x)), ty, Span::default());
+        let let_expr = Expr::new(arena.exprs.alloc(ExprKind::Let(vec![decl], body)), ty, Span::default());
+
+        let replacement = Expr::new(arena.exprs.alloc(ExprKind::Const(sml_frontend::ast::Const::Int(42))), ty, Span::default());
+        let result = substitute(let_expr, x, replacement, &arena);
+
+        match result.expr {
+            ExprKind::Let(decls, body) => {
+                match &decls[0] {
+                    Decl::Val(rule) => match rule.expr.expr {
+                        ExprKind::Const(sml_frontend::ast::Const::Int(42)) => {}
+                        other => panic!("expected the val's RHS to be substituted, got {:?}", other),
+                    },
+                    other => panic!("expected a Decl::Val, got {:?}", other),
+                }
+                match body.expr {
+                    ExprKind::Var(sym) => assert_eq!(*sym, x, "body's `x` should still refer to the decl's own binding"),
+                    other => panic!("expected the body to remain a bare Var, got {:?}", other),
+                }
+            }
+            other => panic!("expected a Let, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn renaming_a_fun_groups_own_name_sweeps_every_sibling() {
+        // `fun even n = ... and odd n = even ...`, substituting for some
+        // unrelated `x` whose replacement happens to have a free `even` —
+        // forcing capture-avoidance to rename this group's own `even` to
+        // dodge it. `odd`'s body calls `even` by name; if the rename only
+        // touched the lambda that owns the name (and not every sibling
+        // that calls it), `odd` would be left calling a symbol nothing in
+        // the group binds anymore.
+        let arena = CoreArena::default();
+        let even = arena.interner.intern("even");
+        let odd = arena.interner.intern("odd");
+        let x = arena.interner.intern("x");
+        let ty: &_ = arena.types.alloc(Type::Var(TypeVar::unbound(0)));
+
+        let even_body = Expr::new(arena.exprs.alloc(ExprKind::Const(sml_frontend::ast::Const::Int(0))), ty, Span::default());
+        let even_lambda = Lambda { arg: even, ty, body: even_body };
+
+        let odd_body = Expr::new(arena.exprs.alloc(ExprKind::Var(even)), ty, Span::default());
+        let odd_lambda = Lambda { arg: odd, ty, body: odd_body };
+
+        let decl = Decl::Fun(Vec::new(), vec![even_lambda, odd_lambda]);
+
+        let replacement = Expr::new(arena.exprs.alloc(ExprKind::Var(even)), ty, Span::default());
+        let free_in_replacement = FreeVars::of(&replacement);
+
+        let (result, binds_var, _renames) = subst_decl(decl, x, replacement, &free_in_replacement, &arena);
+        assert!(!binds_var, "neither `even` nor `odd` is `x`");
+
+        match result {
+            Decl::Fun(_, lambdas) => {
+                let renamed_even = lambdas[0].arg;
+                assert_ne!(renamed_even, even, "the colliding name must have been renamed");
+                match lambdas[1].body.expr {
+                    ExprKind::Var(sym) => assert_eq!(
+                        *sym, renamed_even,
+                        "`odd`'s call to `even` must follow the rename, not reference the stale symbol"
+                    ),
+                    other => panic!("expected `odd`'s body to remain a bare Var, got {:?}", other),
+                }
+            }
+            other => panic!("expected a Decl::Fun, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn renaming_a_vals_pattern_sweeps_the_lets_own_body() {
+        // `let val x = 1 in x end`, substituting unrelated `z` for a
+        // replacement that happens to free-occur `x` — forcing
+        // capture-avoidance to rename this `let`'s own `x` to dodge it.
+        // The body's `x` refers to this same binding, so it must follow
+        // the rename too, not dangle as a reference to a symbol nothing
+        // binds anymore.
+        let arena = CoreArena::default();
+        let x = arena.interner.intern("x");
+        let z = arena.interner.intern("z");
+        let ty: &_ = arena.types.alloc(Type::Var(TypeVar::unbound(0)));
+
+        let rhs = Expr::new(arena.exprs.alloc(ExprKind::Const(sml_frontend::ast::Const::Int(1))), ty, Span::default());
+        let pat = crate::Pat::new(arena.pats.alloc(PatKind::Var(x)), ty, Span::default());
+        let decl = Decl::Val(Rule { pat, expr: rhs });
+
+        let body = Expr::new(arena.exprs.alloc(ExprKind::Var(x)), ty, Span::default());
+        let let_expr = Expr::new(arena.exprs.alloc(ExprKind::Let(vec![decl], body)), ty, Span::default());
+
+        // `z`'s replacement free-occurs `x`, forcing the `let`'s own `x`
+        // to be renamed away so it doesn't capture this `x`.
+        let replacement = Expr::new(arena.exprs.alloc(ExprKind::Var(x)), ty, Span::default());
+        let result = substitute(let_expr, z, replacement, &arena);
+
+        match result.expr {
+            ExprKind::Let(decls, body) => {
+                let renamed_x = match &decls[0] {
+                    Decl::Val(rule) => match rule.pat.pat {
+                        PatKind::Var(sym) => *sym,
+                        other => panic!("expected a Decl::Val(PatKind::Var), got {:?}", other),
+                    },
+                    other => panic!("expected a Decl::Val, got {:?}", other),
+                };
+                assert_ne!(renamed_x, x, "the colliding name must have been renamed");
+                match body.expr {
+                    ExprKind::Var(sym) => assert_eq!(
+                        *sym, renamed_x,
+                        "the body's reference to the let's own binding must follow the rename"
+                    ),
+                    other => panic!("expected the body to remain a bare Var, got {:?}", other),
+                }
+            }
+            other => panic!("expected a Let, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! A visitor over the Core AST.
+//!
+//! [`Visitor`] separates *what to do at a node* (the trait methods) from
+//! *how to descend into its children* (the `walk_*` free functions).
+//! Implementors override only the node kinds they care about and fall back
+//! to `walk_*` for everything else, so a pass like [`FreeVars`] only needs
+//! to handle `Var` and the binders that remove names from scope.
+
+use crate::{Decl, Expr, ExprKind, Lambda, Pat, PatKind, Rule};
+use sml_util::interner::Symbol;
+use std::collections::HashSet;
+
+/// A visitor over `Expr`/`Pat`/`Decl` nodes. Every method has a default
+/// implementation that simply walks into the node's children via the
+/// matching `walk_*` function, so overriding one method doesn't require
+/// reimplementing traversal for the rest of the AST.
+pub trait Visitor<'ar>: Sized {
+    fn visit_expr(&mut self, expr: &Expr<'ar>) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_pat(&mut self, pat: &Pat<'ar>) {
+        walk_pat(self, pat)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl<'ar>) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_rule(&mut self, rule: &Rule<'ar>) {
+        walk_rule(self, rule)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda<'ar>) {
+        walk_lambda(self, lambda)
+    }
+}
+
+/// Recurse into every sub-expression of `expr`.
+pub fn walk_expr<'ar, V: Visitor<'ar>>(visitor: &mut V, expr: &Expr<'ar>) {
+    match expr.expr {
+        ExprKind::App(e1, e2) => {
+            visitor.visit_expr(e1);
+            visitor.visit_expr(e2);
+        }
+        ExprKind::Case(scrutinee, rules) => {
+            visitor.visit_expr(scrutinee);
+            for rule in rules {
+                visitor.visit_rule(rule);
+            }
+        }
+        ExprKind::Con(_, _) => {}
+        ExprKind::Const(_) => {}
+        ExprKind::Handle(body, rules) => {
+            visitor.visit_expr(body);
+            for rule in rules {
+                visitor.visit_rule(rule);
+            }
+        }
+        ExprKind::Lambda(lambda) => visitor.visit_lambda(lambda),
+        ExprKind::Let(decls, body) => {
+            for decl in decls {
+                visitor.visit_decl(decl);
+            }
+            visitor.visit_expr(body);
+        }
+        ExprKind::List(exprs) | ExprKind::Seq(exprs) => {
+            for e in exprs {
+                visitor.visit_expr(e);
+            }
+        }
+        ExprKind::Primitive(_) => {}
+        ExprKind::Raise(e) => visitor.visit_expr(e),
+        ExprKind::Record(rows) => {
+            for row in rows {
+                visitor.visit_expr(&row.data);
+            }
+        }
+        ExprKind::Var(_) => {}
+    }
+}
+
+/// Recurse into every sub-pattern of `pat`.
+pub fn walk_pat<'ar, V: Visitor<'ar>>(visitor: &mut V, pat: &Pat<'ar>) {
+    match pat.pat {
+        PatKind::App(_, Some(sub)) => visitor.visit_pat(sub),
+        PatKind::App(_, None) => {}
+        PatKind::Const(_) => {}
+        PatKind::List(pats) => {
+            for p in pats {
+                visitor.visit_pat(p);
+            }
+        }
+        PatKind::Record(rows) => {
+            for row in rows {
+                visitor.visit_pat(&row.data);
+            }
+        }
+        PatKind::Var(_) => {}
+        PatKind::Wild => {}
+    }
+}
+
+/// Recurse into the declarations making up `decl`.
+pub fn walk_decl<'ar, V: Visitor<'ar>>(visitor: &mut V, decl: &Decl<'ar>) {
+    match decl {
+        Decl::Val(rule) => visitor.visit_rule(rule),
+        Decl::Fun(_, lambdas) => {
+            for lambda in lambdas {
+                visitor.visit_lambda(lambda);
+            }
+        }
+        Decl::Datatype(_) => {}
+        Decl::Exn(_, _) => {}
+    }
+}
+
+/// Visit a rule's pattern and its right-hand side.
+pub fn walk_rule<'ar, V: Visitor<'ar>>(visitor: &mut V, rule: &Rule<'ar>) {
+    visitor.visit_pat(&rule.pat);
+    visitor.visit_expr(&rule.expr);
+}
+
+/// Visit a lambda's body.
+pub fn walk_lambda<'ar, V: Visitor<'ar>>(visitor: &mut V, lambda: &Lambda<'ar>) {
+    visitor.visit_expr(&lambda.body);
+}
+
+/// Collects the set of `Symbol`s that occur free (unbound by an enclosing
+/// `Lambda`, `Let`, or `Rule` pattern) in an `Expr`.
+#[derive(Default)]
+pub struct FreeVars {
+    bound: Vec<Symbol>,
+    free: HashSet<Symbol>,
+}
+
+impl FreeVars {
+    pub fn of<'ar>(expr: &Expr<'ar>) -> HashSet<Symbol> {
+        let mut fv = FreeVars::default();
+        fv.visit_expr(expr);
+        fv.free
+    }
+
+    fn bind_pat<'ar>(&mut self, pat: &Pat<'ar>) {
+        match pat.pat {
+            PatKind::Var(sym) => self.bound.push(*sym),
+            PatKind::App(_, Some(sub)) => self.bind_pat(sub),
+            PatKind::Record(rows) => {
+                for row in rows {
+                    self.bind_pat(&row.data);
+                }
+            }
+            PatKind::List(pats) => {
+                for p in pats {
+                    self.bind_pat(p);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'ar> Visitor<'ar> for FreeVars {
+    fn visit_expr(&mut self, expr: &Expr<'ar>) {
+        match expr.expr {
+            ExprKind::Var(sym) => {
+                if !self.bound.contains(sym) {
+                    self.free.insert(*sym);
+                }
+            }
+            ExprKind::Lambda(lambda) => {
+                self.bound.push(lambda.arg);
+                self.visit_expr(&lambda.body);
+                self.bound.pop();
+            }
+            ExprKind::Let(decls, body) => {
+                let mark = self.bound.len();
+                for decl in decls {
+                    self.visit_decl(decl);
+                }
+                self.visit_expr(body);
+                self.bound.truncate(mark);
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_rule(&mut self, rule: &Rule<'ar>) {
+        let mark = self.bound.len();
+        self.bind_pat(&rule.pat);
+        self.visit_expr(&rule.expr);
+        self.bound.truncate(mark);
+    }
+
+    fn visit_decl(&mut self, decl: &Decl<'ar>) {
+        match decl {
+            Decl::Fun(_, lambdas) => {
+                for lambda in lambdas {
+                    self.bound.push(lambda.arg);
+                }
+                for lambda in lambdas {
+                    self.visit_expr(&lambda.body);
+                }
+            }
+            // Unlike a Case/Handle rule, `val pat = expr` evaluates `expr`
+            // in the *enclosing* scope: the pattern's bindings are only in
+            // scope for what follows this decl, not for its own
+            // right-hand side. Visit `expr` before binding `pat`.
+            Decl::Val(rule) => {
+                self.visit_expr(&rule.expr);
+                self.bind_pat(&rule.pat);
+            }
+            _ => walk_decl(self, decl),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Type, TypeVar};
+    use crate::{Decl, Expr as CoreExpr, Rule};
+    use sml_util::interner::Interner;
+    use sml_util::span::Span;
+
+    #[test]
+    fn val_rhs_is_evaluated_in_the_enclosing_scope() {
+        // `let val x = x in x end`: the `x` on the right of `=` refers to
+        // an outer binding, not the one this decl introduces, so it must
+        // show up as free. The `x` in the body, on the other hand, refers
+        // to this decl's binding and must not.
+        let mut interner = Interner::with_capacity(8);
+        let x = interner.intern("x");
+        let ty = Type::Var(TypeVar::unbound(0));
+
+        let rhs_kind = ExprKind::Var(x);
+        let rhs = CoreExpr::new(&rhs_kind, &ty, Span::default());
+        let pat_kind = PatKind::Var(x);
+        let pat = crate::Pat::new(&pat_kind, &ty, Span::default());
+        let decl = Decl::Val(Rule { pat, expr: rhs });
+
+        let body_kind = ExprKind::Var(x);
+        let body = CoreExpr::new(&body_kind, &ty, Span::default());
+        let let_kind = ExprKind::Let(vec![decl], body);
+        let let_expr = CoreExpr::new(&let_kind, &ty, Span::default());
+
+        let free = FreeVars::of(&let_expr);
+        assert!(free.contains(&x), "expected the val's own right-hand side `x` to be free");
+    }
+}
@@ -0,0 +1,181 @@
+//! The long-lived driver state shared by a one-shot compile, `--repl`, and
+//! `--watch`: an arena, a persistent elaboration context, and the handful
+//! of knobs `ArgParse`/`CompilerBuilder` read off the command line.
+
+use crate::watch::{Diagnostic, Severity};
+use sml_core::arenas::CoreArena;
+use sml_core::elaborate::Context;
+use sml_core::eval::{self, Flow};
+use sml_core::serialize;
+use sml_core::types::Scheme;
+use sml_core::{Decl, PatKind};
+use sml_frontend::parser::Parser;
+use sml_util::interner::{Interner, Symbol};
+use std::fs::File;
+use std::time::Duration;
+
+pub struct Compiler<'a> {
+    pub(crate) arena: &'a CoreArena<'a>,
+    pub(crate) src: String,
+    pub(crate) elab: Context<'a>,
+    pub(crate) interner: Interner,
+    pub(crate) measure: bool,
+    pub(crate) verbosity: u8,
+    pub(crate) stop_phase: String,
+    pub(crate) interactive: bool,
+    pub(crate) emit_core: Option<String>,
+    pub(crate) load_core: Option<String>,
+    pub(crate) watch: bool,
+    pub(crate) times: Vec<(&'static str, Duration)>,
+}
+
+impl<'a> Compiler<'a> {
+    /// Parse and elaborate `src` against the persistent context. If
+    /// `load_core` names a cache from a previous `emit_core` run, that
+    /// cache is decoded and returned directly, skipping parse/elaborate
+    /// entirely; otherwise, on success, an `emit_core` path gets the
+    /// freshly elaborated `Decl`s written back out for next time.
+    fn elaborate_program(&mut self, src: &str) -> Result<Vec<Decl<'a>>, Vec<Diagnostic>> {
+        if let Some(path) = &self.load_core {
+            let mut file = File::open(path).map_err(|e| {
+                vec![Diagnostic {
+                    span: Default::default(),
+                    severity: Severity::Error,
+                    message: format!("couldn't open core cache {}: {}", path, e),
+                }]
+            })?;
+            return serialize::decode(&mut file, self.arena).map_err(|e| {
+                vec![Diagnostic {
+                    span: Default::default(),
+                    severity: Severity::Error,
+                    message: format!("couldn't decode core cache {}: {}", path, e),
+                }]
+            });
+        }
+
+        let ast = Parser::new(src, &mut self.interner).parse_program().map_err(|e| {
+            vec![Diagnostic { span: e.span, severity: Severity::Error, message: e.message }]
+        })?;
+        let decls = self.elab.elaborate_program(ast).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|e| Diagnostic { span: e.span, severity: Severity::Error, message: e.message })
+                .collect()
+        })?;
+
+        if let Some(path) = &self.emit_core {
+            let mut file = File::create(path).map_err(|e| {
+                vec![Diagnostic {
+                    span: Default::default(),
+                    severity: Severity::Error,
+                    message: format!("couldn't write core cache {}: {}", path, e),
+                }]
+            })?;
+            serialize::encode(&decls, &self.interner, &mut file).map_err(|e| {
+                vec![Diagnostic {
+                    span: Default::default(),
+                    severity: Severity::Error,
+                    message: format!("couldn't encode core cache {}: {}", path, e),
+                }]
+            })?;
+        }
+
+        Ok(decls)
+    }
+
+    /// Elaborate one REPL chunk against the persistent `elab` context (so
+    /// earlier chunks stay in scope), returning the inferred [`Scheme`] of
+    /// every name the chunk binds at its top level.
+    pub fn elaborate_chunk(&mut self, src: &str) -> Result<Vec<(Symbol, Scheme)>, Vec<Diagnostic>> {
+        let decls = self.elaborate_program(src)?;
+        let mut schemes = Vec::new();
+        for decl in &decls {
+            match decl {
+                Decl::Val(rule) => {
+                    if let PatKind::Var(sym) = rule.pat.pat {
+                        if let Some(scheme) = self.elab.scheme_of(*sym) {
+                            schemes.push((*sym, scheme));
+                        }
+                    }
+                }
+                // Each `Lambda` in a `Decl::Fun` group binds the function's
+                // own name via `.arg` (see `eval::eval_decl`'s `Decl::Fun`
+                // arm, and `FreeVars::visit_decl`), so report a scheme for
+                // every `lam.arg` the same way a `Decl::Val(PatKind::Var)`
+                // is reported above.
+                Decl::Fun(_, lambdas) => {
+                    for lam in lambdas {
+                        if let Some(scheme) = self.elab.scheme_of(lam.arg) {
+                            schemes.push((lam.arg, scheme));
+                        }
+                    }
+                }
+                Decl::Datatype(_) | Decl::Exn(_, _) => {}
+            }
+        }
+        Ok(schemes)
+    }
+
+    /// Re-read and re-elaborate `files` from scratch, as `--watch` does on
+    /// every change. Unlike [`Compiler::elaborate_chunk`], this starts from
+    /// a fresh `elab` context each time: a file that dropped a binding
+    /// should actually lose it, rather than have it linger from the
+    /// previous rebuild.
+    pub fn rebuild(&mut self, files: &[String]) -> Vec<Diagnostic> {
+        let mut src = String::new();
+        for file in files {
+            match std::fs::read_to_string(file) {
+                Ok(contents) => {
+                    src.push_str(&contents);
+                    src.push('\n');
+                }
+                Err(e) => {
+                    return vec![Diagnostic {
+                        span: Default::default(),
+                        severity: Severity::Error,
+                        message: format!("couldn't read {}: {}", file, e),
+                    }]
+                }
+            }
+        }
+
+        self.elab = Context::new(self.arena);
+        self.src = src;
+        self.run()
+    }
+
+    /// Run the one-shot pipeline over `self.src`, returning whatever
+    /// diagnostics the parse/elaborate pipeline produced, plus an uncaught
+    /// exception under `--phase eval` (empty on success). This is the same
+    /// list `--watch` prints after every
+    /// rebuild. When `--phase eval` was requested, this is also what
+    /// actually runs the program: `stop_phase` otherwise only labels where
+    /// the pipeline would stop, so without this the evaluator added for
+    /// `--phase eval` would never be reachable from the CLI.
+    pub fn run(&mut self) -> Vec<Diagnostic> {
+        let src = self.src.clone();
+        let decls = match self.elaborate_program(&src) {
+            Ok(decls) => decls,
+            Err(diagnostics) => return diagnostics,
+        };
+
+        if self.stop_phase == "eval" {
+            match eval::eval_decls(&decls, &eval::Env::new()) {
+                Ok(env) => {
+                    for (name, value) in &env {
+                        println!("val {} = {:?}", name, value);
+                    }
+                }
+                Err(Flow::Raise(value)) => {
+                    return vec![Diagnostic {
+                        span: Default::default(),
+                        severity: Severity::Error,
+                        message: format!("uncaught exception: {:?}", value),
+                    }]
+                }
+            }
+        }
+
+        Vec::new()
+    }
+}
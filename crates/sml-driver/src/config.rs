@@ -7,6 +7,10 @@ pub struct CompilerBuilder {
     measure: Option<bool>,
     verbosity: Option<u8>,
     phase: Option<String>,
+    interactive: Option<bool>,
+    emit_core: Option<String>,
+    load_core: Option<String>,
+    watch: Option<bool>,
 }
 
 impl CompilerBuilder {
@@ -19,6 +23,10 @@ impl CompilerBuilder {
             measure: self.measure.unwrap_or(false),
             verbosity: self.verbosity.unwrap_or(0),
             stop_phase: self.phase.unwrap_or_default(),
+            interactive: self.interactive.unwrap_or(false),
+            emit_core: self.emit_core,
+            load_core: self.load_core,
+            watch: self.watch.unwrap_or(false),
             times: Vec::new(),
         }
     }
@@ -37,6 +45,26 @@ impl CompilerBuilder {
         self.measure = Some(val);
         self
     }
+
+    pub fn interactive(mut self, val: bool) -> Self {
+        self.interactive = Some(val);
+        self
+    }
+
+    pub fn emit_core(mut self, val: String) -> Self {
+        self.emit_core = Some(val);
+        self
+    }
+
+    pub fn load_core(mut self, val: String) -> Self {
+        self.load_core = Some(val);
+        self
+    }
+
+    pub fn watch(mut self, val: bool) -> Self {
+        self.watch = Some(val);
+        self
+    }
 }
 
 pub struct ArgParse {
@@ -65,6 +93,20 @@ impl ArgParse {
                     "--measure" => {
                         builder = builder.measure(true);
                     }
+                    "--repl" => {
+                        builder = builder.interactive(true);
+                    }
+                    "--emit-core" => {
+                        let path = stack.pop().expect("expected path after --emit-core");
+                        builder = builder.emit_core(path);
+                    }
+                    "--load-core" => {
+                        let path = stack.pop().expect("expected path after --load-core");
+                        builder = builder.load_core(path);
+                    }
+                    "--watch" => {
+                        builder = builder.watch(true);
+                    }
                     "--phase" => {
                         let phase =
                             match stack.pop().expect("expected phase after --phase").as_ref() {
@@ -72,6 +114,7 @@ impl ArgParse {
                                 "elab" => "elaborate".into(),
                                 "mono" => "monomorphize".into(),
                                 "flat" => "flatten".into(),
+                                "eval" => "eval".into(),
                                 item => panic!("unrecognized compiler phase: {}", item),
                             };
                         builder = builder.phase(phase);
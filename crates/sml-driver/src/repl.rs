@@ -0,0 +1,140 @@
+//! An interactive, multi-line read-eval-print loop.
+//!
+//! Unlike a one-shot file compile, the REPL keeps its `elaborate::Context`
+//! and `Interner` alive across inputs, so a datatype or value bound in one
+//! line stays in scope for the next. Input is accumulated until
+//! [`chunk_is_balanced`] judges it complete, which lets users write a
+//! multi-line `fun`, `let ... in ... end`, or `case` without the prompt
+//! mistaking an intermediate line for a syntax error. That check is a
+//! token-level heuristic, not a parse: a chunk that passes it but does not
+//! parse is reported as an error and discarded.
+
+use crate::compiler::Compiler;
+use std::io::{self, Write};
+
+const PRIMARY_PROMPT: &str = "- ";
+const CONTINUATION_PROMPT: &str = "= ";
+
+/// Run the interactive loop against `compiler` until EOF (Ctrl-D) or an
+/// explicit `quit();`.
+pub fn run<'a>(compiler: &mut Compiler<'a>) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut buffer = String::new();
+
+    loop {
+        if buffer.is_empty() {
+            print!("{}", PRIMARY_PROMPT);
+        } else {
+            print!("{}", CONTINUATION_PROMPT);
+        }
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            // EOF
+            println!();
+            return Ok(());
+        }
+
+        buffer.push_str(&line);
+
+        if buffer.trim() == "quit();" {
+            return Ok(());
+        }
+
+        if !chunk_is_balanced(&buffer) {
+            continue;
+        }
+
+        match compiler.elaborate_chunk(&buffer) {
+            Ok(schemes) => {
+                for (name, scheme) in schemes {
+                    println!("val {} : {:?}", name, scheme);
+                }
+            }
+            Err(diagnostics) => {
+                for diag in diagnostics {
+                    eprintln!("{:?}", diag);
+                }
+            }
+        }
+
+        buffer.clear();
+    }
+}
+
+/// Roughly determine whether `src` forms a syntactically complete chunk:
+/// every paren/bracket is closed, every `let`/`end` has been matched, and
+/// the chunk doesn't end mid-binding — a trailing `=`/`of` (an incomplete
+/// `fun`/`val`/`case`) or `=>`/`|` (a rule waiting on its right-hand side
+/// or the next clause) all mean there's more coming. This is intentionally
+/// conservative — false negatives just mean we prompt for one more line,
+/// which is cheap; false positives would hand the parser a chunk it can't
+/// finish, so we err on the side of buffering longer.
+fn chunk_is_balanced(src: &str) -> bool {
+    let mut parens = 0i32;
+    let mut let_depth = 0i32;
+    let mut last_tok: Option<&str> = None;
+
+    for tok in src.split_whitespace() {
+        for c in tok.chars() {
+            match c {
+                '(' | '[' | '{' => parens += 1,
+                ')' | ']' | '}' => parens -= 1,
+                _ => {}
+            }
+        }
+        match tok.trim_matches(|c: char| !c.is_alphanumeric()) {
+            "let" => let_depth += 1,
+            "end" => let_depth -= 1,
+            _ => {}
+        }
+        last_tok = Some(tok);
+    }
+
+    let dangling = matches!(last_tok, Some("=") | Some("=>") | Some("of") | Some("|"));
+
+    parens <= 0 && let_depth <= 0 && !dangling
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_fun_binding_is_not_balanced() {
+        assert!(!chunk_is_balanced("fun fact n ="));
+    }
+
+    #[test]
+    fn incomplete_case_is_not_balanced() {
+        assert!(!chunk_is_balanced("case n of"));
+    }
+
+    #[test]
+    fn rule_awaiting_its_next_clause_is_not_balanced() {
+        assert!(!chunk_is_balanced("case n of 0 => 1 |"));
+    }
+
+    #[test]
+    fn complete_fun_binding_is_balanced() {
+        assert!(chunk_is_balanced("fun fact n = if n = 0 then 1 else n * fact (n - 1)"));
+    }
+
+    #[test]
+    fn complete_let_is_balanced() {
+        assert!(chunk_is_balanced("let val x = 1 in x end"));
+    }
+
+    #[test]
+    fn unclosed_paren_is_not_balanced() {
+        assert!(!chunk_is_balanced("val x = (1 + 2"));
+    }
+
+    #[test]
+    fn parenthesized_inner_let_missing_outer_end_is_not_balanced() {
+        assert!(!chunk_is_balanced(
+            "let val x = (let val y = 1 in y end)"
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+//! `--watch` mode: keep the process alive, re-run the pipeline whenever an
+//! input file changes, and report the results as a batch of diagnostics
+//! rather than panicking or printing ad-hoc text.
+//!
+//! The same `Diagnostic` list this loop prints is also handed back from
+//! `Compiler::run`, so an editor/LSP integration can reuse this exact
+//! machinery instead of re-implementing "run the pipeline, collect what
+//! went wrong".
+
+use crate::compiler::Compiler;
+use sml_util::span::Span;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watch `files` for modification, re-running `compiler` over all of them
+/// on every change, until interrupted.
+pub fn run<'a>(compiler: &mut Compiler<'a>, files: &[String]) -> ! {
+    let mut mtimes: HashMap<&str, SystemTime> = HashMap::new();
+    for file in files {
+        if let Ok(time) = modified(file) {
+            mtimes.insert(file.as_str(), time);
+        }
+    }
+
+    // Run once up front so the first rebuild isn't gated on a change.
+    rebuild(compiler, files);
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let mut changed = false;
+        for file in files {
+            if let Ok(time) = modified(file) {
+                match mtimes.get(file.as_str()) {
+                    Some(prev) if *prev == time => {}
+                    _ => {
+                        mtimes.insert(file.as_str(), time);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        if changed {
+            rebuild(compiler, files);
+        }
+    }
+}
+
+fn rebuild<'a>(compiler: &mut Compiler<'a>, files: &[String]) {
+    // Clear the previous batch first so the terminal reflects only the
+    // current state of the tree, not an accumulation of every rebuild.
+    print!("\x1B[2J\x1B[1;1H");
+
+    let diagnostics = compiler.rebuild(files);
+    if diagnostics.is_empty() {
+        println!("ok");
+    } else {
+        for diag in &diagnostics {
+            let label = match diag.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            println!("{}: {:?}: {}", label, diag.span, diag.message);
+        }
+    }
+}
+
+fn modified(path: &str) -> std::io::Result<SystemTime> {
+    fs::metadata(Path::new(path))?.modified()
+}